@@ -1,26 +1,137 @@
-use std::{env, fmt};
+use htmlisp::{FormatOptions, WrapAttributes};
+use std::{
+    fmt,
+    io::{self, IsTerminal},
+    path::Path,
+};
 
-#[derive(Debug)]
+/// What the program was asked to do
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    Run(Subcommand),
+    /// Print usage, for one subcommand or for the whole program
+    Help(Option<Subcommand>),
+    Version,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Subcommand {
+    /// Compile an HTMLisp file to HTML
+    Build,
+    /// Re-compile files in a directory as they change
+    Watch,
+    /// Rewrite HTMLisp files in the canonical style
+    Fmt,
+    /// Report errors in HTMLisp files without writing anything
+    Check,
+    /// Convert an HTML file to HTMLisp
+    Convert,
+}
+
+impl Subcommand {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "build" => Some(Subcommand::Build),
+            "watch" => Some(Subcommand::Watch),
+            "fmt" => Some(Subcommand::Fmt),
+            "check" => Some(Subcommand::Check),
+            "convert" => Some(Subcommand::Convert),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Subcommand::Build => "build",
+            Subcommand::Watch => "watch",
+            Subcommand::Fmt => "fmt",
+            Subcommand::Check => "check",
+            Subcommand::Convert => "convert",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Config {
-    pub help: bool,
+    pub command: Command,
     pub prettify: bool,
+    pub format: FormatOptions,
+    pub doctype: bool,
+    pub xhtml: bool,
+    /// The directory `watch` compiles files in
     pub watch: String,
+    /// Input and output for `build` and `convert`, `-` meaning stdin or stdout. For `watch` and
+    /// building a directory, `output_file` is the directory to write to
     pub input_file: String,
     pub output_file: String,
+    /// The extension given to files compiled from a directory
+    pub output_extension: String,
+    /// Only report files that aren't formatted rather than rewriting them
+    pub check: bool,
+    /// Files for `fmt` and `check`, `-` meaning stdin
+    pub files: Vec<String>,
 }
 
 impl Config {
-    pub fn new(args: &mut env::Args) -> Result<Self, ArgsError> {
-        args.next();
+    /// Read the configuration from the arguments after the program's name
+    pub fn new(args: impl IntoIterator<Item = String>) -> Result<Self, ArgsError> {
+        let args: Vec<String> = args.into_iter().collect();
+
+        // Without a subcommand, the original flags choose what to do
+        let (subcommand, explicit, args) = match args.first().and_then(|s| Subcommand::from_name(s))
+        {
+            Some(subcommand) => (subcommand, true, &args[1..]),
+            None => {
+                let flags = args.iter().take_while(|arg| *arg != "--");
+                let subcommand = match flags
+                    .filter(|arg| ["-w", "--watch", "--html2lisp"].contains(&arg.as_str()))
+                    .last()
+                {
+                    Some(flag) if flag == "--html2lisp" => Subcommand::Convert,
+                    Some(_) => Subcommand::Watch,
+                    None => Subcommand::Build,
+                };
+                (subcommand, false, &args[..])
+            }
+        };
 
         let mut cfg = Config {
-            help: false,
+            command: Command::Run(subcommand),
             prettify: false,
+            format: FormatOptions::default(),
+            doctype: false,
+            xhtml: false,
             watch: String::new(),
             input_file: String::new(),
             output_file: String::new(),
+            output_extension: "html".to_string(),
+            check: false,
+            files: vec![],
         };
+        // Rather than waiting on a terminal for input, show what the program can do
+        if args.is_empty() && !explicit && io::stdin().is_terminal() {
+            cfg.command = Command::Help(None);
+            return Ok(cfg);
+        }
+        let mut positional = vec![];
+        let mut args = args.iter().cloned();
         while let Some(arg) = args.next() {
+            let allowed: &[Subcommand] = match arg.as_str() {
+                "-i" | "--input" => &[Subcommand::Build, Subcommand::Convert],
+                "-o" | "--output" => &[Subcommand::Build, Subcommand::Convert, Subcommand::Watch],
+                "--extension" => &[Subcommand::Build, Subcommand::Watch],
+                "-p" | "--prettify" | "--indent" | "--max-width" | "--wrap-attributes"
+                | "--trailing-newline" | "-d" | "--doctype" | "--xhtml" => {
+                    &[Subcommand::Build, Subcommand::Watch]
+                }
+                "--check" => &[Subcommand::Fmt],
+                "-w" | "--watch" | "--html2lisp" if explicit => &[],
+                _ => &[subcommand],
+            };
+            if !allowed.contains(&subcommand) {
+                return Err(ArgsError::NotAllowed(arg, subcommand.name()));
+            }
+
             match arg.as_str() {
                 "-i" | "--input" => {
                     cfg.input_file = args.next().ok_or(ArgsError::InputMissing)?;
@@ -31,30 +142,133 @@ impl Config {
                 "-p" | "--prettify" => {
                     cfg.prettify = true;
                 }
+                // Formatting flags only make sense for prettified output, so imply it
+                "--indent" => {
+                    let indent = args.next().ok_or(ArgsError::ValueMissing(arg))?;
+                    cfg.format.indent = match indent.parse::<usize>() {
+                        _ if indent == "tab" => "\t".to_string(),
+                        Ok(spaces) => " ".repeat(spaces),
+                        Err(_) => {
+                            return Err(ArgsError::InvalidValue("--indent".to_string(), indent))
+                        }
+                    };
+                    cfg.prettify = true;
+                }
+                "--max-width" => {
+                    let width = args.next().ok_or(ArgsError::ValueMissing(arg))?;
+                    cfg.format.max_width = match width.parse::<usize>() {
+                        Ok(width) => Some(width),
+                        Err(_) => {
+                            return Err(ArgsError::InvalidValue("--max-width".to_string(), width))
+                        }
+                    };
+                    cfg.prettify = true;
+                }
+                "--wrap-attributes" => {
+                    let wrap = args.next().ok_or(ArgsError::ValueMissing(arg))?;
+                    cfg.format.wrap_attributes = match wrap.as_str() {
+                        "never" => WrapAttributes::Never,
+                        "auto" => WrapAttributes::Auto,
+                        "always" => WrapAttributes::Always,
+                        _ => {
+                            return Err(ArgsError::InvalidValue(
+                                "--wrap-attributes".to_string(),
+                                wrap,
+                            ))
+                        }
+                    };
+                    cfg.prettify = true;
+                }
+                "--trailing-newline" => {
+                    cfg.format.trailing_newline = true;
+                    cfg.prettify = true;
+                }
+                "-d" | "--doctype" => {
+                    cfg.doctype = true;
+                }
+                "--xhtml" => {
+                    cfg.xhtml = true;
+                }
+                "--extension" => {
+                    let extension = args.next().ok_or(ArgsError::ValueMissing(arg))?;
+                    cfg.output_extension = extension.trim_start_matches('.').to_string();
+                }
                 "-w" | "--watch" => {
                     cfg.watch = args.next().ok_or(ArgsError::WatchDirMissing)?;
                 }
+                "--html2lisp" => {}
+                "--check" => {
+                    cfg.check = true;
+                }
                 "-h" | "--help" => {
-                    cfg.help = true;
+                    cfg.command = Command::Help(Some(subcommand).filter(|_| explicit));
+                }
+                "-V" | "--version" => {
+                    cfg.command = Command::Version;
                 }
-                unknown => return Err(ArgsError::UnknownArg(unknown.to_string())),
+                // Everything after `--` is positional, even if it looks like a flag
+                "--" => positional.extend(args.by_ref()),
+                // A lone `-` is stdin or stdout
+                flag if flag.starts_with('-') && flag != "-" => {
+                    return Err(ArgsError::UnknownArg(flag.to_string()))
+                }
+                _ => positional.push(arg),
             }
         }
 
-        if cfg.input_file.is_empty() && !cfg.help && cfg.watch.is_empty() {
-            return Err(ArgsError::InputMissing);
-        } else if cfg.output_file.is_empty() && !cfg.help && cfg.watch.is_empty() {
-            return Err(ArgsError::OutputMissing);
+        // Help and the version don't need anything else to be valid
+        if cfg.command != Command::Run(subcommand) {
+            return Ok(cfg);
+        }
+
+        let mut positional = positional.into_iter();
+        match subcommand {
+            Subcommand::Build | Subcommand::Convert => {
+                // Missing files default to stdin and stdout, for use in pipelines
+                for file in [&mut cfg.input_file, &mut cfg.output_file] {
+                    if file.is_empty() {
+                        *file = positional.next().unwrap_or_else(|| "-".to_string());
+                    }
+                }
+            }
+            Subcommand::Watch => {
+                if cfg.watch.is_empty() {
+                    cfg.watch = positional.next().ok_or(ArgsError::WatchDirMissing)?;
+                }
+                // Output goes inside the watched directory unless told otherwise, so it doesn't
+                // depend on where the program is run from
+                if cfg.output_file.is_empty() {
+                    cfg.output_file = Path::new(&cfg.watch)
+                        .join("output")
+                        .to_string_lossy()
+                        .into_owned();
+                }
+            }
+            Subcommand::Fmt | Subcommand::Check => {
+                cfg.files = positional.by_ref().collect();
+                if cfg.files.is_empty() {
+                    return Err(ArgsError::FilesMissing);
+                }
+            }
+        }
+        if let Some(extra) = positional.next() {
+            return Err(ArgsError::UnexpectedArg(extra));
         }
 
         Ok(cfg)
     }
 }
 
+#[derive(Debug, PartialEq)]
 pub enum ArgsError {
     InputMissing,
     OutputMissing,
     WatchDirMissing,
+    FilesMissing,
+    ValueMissing(String),
+    InvalidValue(String, String),
+    NotAllowed(String, &'static str),
+    UnexpectedArg(String),
     UnknownArg(String),
 }
 
@@ -67,8 +281,215 @@ impl fmt::Display for ArgsError {
                 ArgsError::InputMissing => "Input file not specified".to_string(),
                 ArgsError::OutputMissing => "Output file not specified".to_string(),
                 ArgsError::WatchDirMissing => "Directory to watch not specified".to_string(),
+                ArgsError::FilesMissing => "Files not specified".to_string(),
+                ArgsError::ValueMissing(flag) => format!("Value for '{}' not specified", flag),
+                ArgsError::InvalidValue(flag, value) =>
+                    format!("Invalid value '{}' for '{}'", value, flag),
+                ArgsError::NotAllowed(flag, subcommand) =>
+                    format!("'{}' can't be used with '{}'", flag, subcommand),
+                ArgsError::UnexpectedArg(s) => format!("Unexpected argument '{}'", s),
                 ArgsError::UnknownArg(s) => format!("Unknown flag '{}'", s),
             }
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(args: &[&str]) -> Result<Config, ArgsError> {
+        Config::new(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn not_allowed(flag: &str, subcommand: &'static str) -> Result<Command, ArgsError> {
+        Err(ArgsError::NotAllowed(flag.to_string(), subcommand))
+    }
+
+    #[test]
+    fn subcommands() {
+        let cfg = config(&["build", "in.htmlisp", "out.html"]).unwrap();
+        assert_eq!(cfg.command, Command::Run(Subcommand::Build));
+        assert_eq!(
+            (&*cfg.input_file, &*cfg.output_file),
+            ("in.htmlisp", "out.html")
+        );
+
+        let cfg = config(&["convert", "-o", "out.htmlisp", "in.html"]).unwrap();
+        assert_eq!(cfg.command, Command::Run(Subcommand::Convert));
+        assert_eq!(
+            (&*cfg.input_file, &*cfg.output_file),
+            ("in.html", "out.htmlisp")
+        );
+
+        let cfg = config(&["watch", "site", "--extension", ".htm"]).unwrap();
+        assert_eq!(cfg.command, Command::Run(Subcommand::Watch));
+        assert_eq!(cfg.watch, "site");
+        assert_eq!(
+            cfg.output_file,
+            Path::new("site").join("output").to_str().unwrap()
+        );
+        assert_eq!(cfg.output_extension, "htm");
+
+        let cfg = config(&["fmt", "--check", "a.htmlisp", "b.htmlisp"]).unwrap();
+        assert_eq!(cfg.command, Command::Run(Subcommand::Fmt));
+        assert!(cfg.check);
+        assert_eq!(cfg.files, ["a.htmlisp", "b.htmlisp"]);
+
+        let cfg = config(&["check", "a.htmlisp"]).unwrap();
+        assert_eq!(cfg.command, Command::Run(Subcommand::Check));
+        assert_eq!(cfg.files, ["a.htmlisp"]);
+    }
+
+    #[test]
+    fn legacy_flags() {
+        let cfg = config(&["-i", "in.htmlisp", "-o", "out.html", "-p"]).unwrap();
+        assert_eq!(cfg.command, Command::Run(Subcommand::Build));
+        assert_eq!(
+            (&*cfg.input_file, &*cfg.output_file),
+            ("in.htmlisp", "out.html")
+        );
+        assert!(cfg.prettify);
+
+        let cfg = config(&["-w", "site", "-o", "public"]).unwrap();
+        assert_eq!(cfg.command, Command::Run(Subcommand::Watch));
+        assert_eq!((&*cfg.watch, &*cfg.output_file), ("site", "public"));
+
+        let cfg = config(&["--html2lisp", "-i", "in.html"]).unwrap();
+        assert_eq!(cfg.command, Command::Run(Subcommand::Convert));
+        assert_eq!((&*cfg.input_file, &*cfg.output_file), ("in.html", "-"));
+
+        // The last of the flags choosing a mode wins, and flags after `--` aren't looked at
+        let cfg = config(&["-w", "site", "--html2lisp"]).unwrap();
+        assert_eq!(cfg.command, Command::Run(Subcommand::Convert));
+        let cfg = config(&["--", "-w"]).unwrap();
+        assert_eq!(cfg.command, Command::Run(Subcommand::Build));
+        assert_eq!(cfg.input_file, "-w");
+    }
+
+    #[test]
+    fn positional_arguments() {
+        let cfg = config(&["build"]).unwrap();
+        assert_eq!((&*cfg.input_file, &*cfg.output_file), ("-", "-"));
+
+        let cfg = config(&["build", "-", "out.html"]).unwrap();
+        assert_eq!((&*cfg.input_file, &*cfg.output_file), ("-", "out.html"));
+
+        let cfg = config(&["build", "--", "-in.htmlisp", "--help"]).unwrap();
+        assert_eq!(cfg.command, Command::Run(Subcommand::Build));
+        assert_eq!(
+            (&*cfg.input_file, &*cfg.output_file),
+            ("-in.htmlisp", "--help")
+        );
+
+        let cfg = config(&["fmt", "-", "--", "-a.htmlisp"]).unwrap();
+        assert_eq!(cfg.files, ["-", "-a.htmlisp"]);
+
+        assert_eq!(
+            config(&["build", "a", "b", "c"]).unwrap_err(),
+            ArgsError::UnexpectedArg("c".to_string())
+        );
+        assert_eq!(
+            config(&["watch", "site", "public"]).unwrap_err(),
+            ArgsError::UnexpectedArg("public".to_string())
+        );
+        assert_eq!(config(&["fmt"]).unwrap_err(), ArgsError::FilesMissing);
+        assert_eq!(config(&["watch"]).unwrap_err(), ArgsError::WatchDirMissing);
+    }
+
+    #[test]
+    fn flags_allowed_per_subcommand() {
+        let command = |args: &[&str]| config(args).map(|cfg| cfg.command);
+        assert_eq!(command(&["fmt", "-p", "a"]), not_allowed("-p", "fmt"));
+        assert_eq!(
+            command(&["check", "--xhtml", "a"]),
+            not_allowed("--xhtml", "check")
+        );
+        assert_eq!(
+            command(&["build", "--check"]),
+            not_allowed("--check", "build")
+        );
+        assert_eq!(
+            command(&["watch", "-i", "a", "site"]),
+            not_allowed("-i", "watch")
+        );
+        assert_eq!(
+            command(&["convert", "--extension", "htm"]),
+            not_allowed("--extension", "convert")
+        );
+        assert_eq!(command(&["fmt", "-w", "site"]), not_allowed("-w", "fmt"));
+        assert_eq!(
+            command(&["build", "--html2lisp"]),
+            not_allowed("--html2lisp", "build")
+        );
+        assert_eq!(
+            command(&["-w", "site", "--check"]),
+            not_allowed("--check", "watch")
+        );
+        assert_eq!(
+            command(&["--html2lisp", "-d"]),
+            not_allowed("-d", "convert")
+        );
+    }
+
+    #[test]
+    fn flag_values() {
+        let cfg = config(&[
+            "build",
+            "--indent",
+            "2",
+            "--max-width",
+            "80",
+            "--wrap-attributes",
+            "always",
+        ])
+        .unwrap();
+        assert!(cfg.prettify);
+        assert_eq!(cfg.format.indent, "  ");
+        assert_eq!(cfg.format.max_width, Some(80));
+        assert_eq!(cfg.format.wrap_attributes, WrapAttributes::Always);
+
+        let cfg = config(&["build", "--indent", "tab", "-d", "--xhtml"]).unwrap();
+        assert_eq!(cfg.format.indent, "\t");
+        assert!(cfg.doctype && cfg.xhtml);
+
+        assert_eq!(
+            config(&["build", "--indent", "wide"]).unwrap_err(),
+            ArgsError::InvalidValue("--indent".to_string(), "wide".to_string())
+        );
+        assert_eq!(
+            config(&["build", "--max-width"]).unwrap_err(),
+            ArgsError::ValueMissing("--max-width".to_string())
+        );
+        assert_eq!(config(&["-i"]).unwrap_err(), ArgsError::InputMissing);
+        assert_eq!(config(&["-w"]).unwrap_err(), ArgsError::WatchDirMissing);
+        assert_eq!(
+            config(&["build", "--bogus"]).unwrap_err(),
+            ArgsError::UnknownArg("--bogus".to_string())
+        );
+    }
+
+    #[test]
+    fn help_and_version() {
+        let command = |args: &[&str]| config(args).map(|cfg| cfg.command);
+        assert_eq!(command(&["-h"]), Ok(Command::Help(None)));
+        assert_eq!(command(&["-w", "site", "--help"]), Ok(Command::Help(None)));
+        assert_eq!(
+            command(&["fmt", "--help"]),
+            Ok(Command::Help(Some(Subcommand::Fmt)))
+        );
+        assert_eq!(
+            command(&["build", "a", "b", "c", "-h"]),
+            Ok(Command::Help(Some(Subcommand::Build)))
+        );
+        assert_eq!(command(&["watch", "-V"]), Ok(Command::Version));
+        assert_eq!(command(&["--version", "--help"]), Ok(Command::Help(None)));
+        assert_eq!(command(&["--help", "--version"]), Ok(Command::Version));
+        // Flags are still checked, even when only asking for help
+        assert_eq!(command(&["fmt", "-p", "--help"]), not_allowed("-p", "fmt"));
+        assert_eq!(
+            command(&["--help", "--bogus"]),
+            Err(ArgsError::UnknownArg("--bogus".to_string()))
+        );
+    }
+}
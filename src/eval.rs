@@ -0,0 +1,389 @@
+use crate::parser::{ends_processing_instruction, location, Document, Node, Value};
+use crate::render::closes_raw_text;
+use core::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvalError {
+    /// Byte offset into the input
+    pub offset: usize,
+    /// 1-based line number
+    pub line: usize,
+    /// 1-based column, counted in characters
+    pub column: usize,
+    pub kind: EvalErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EvalErrorKind {
+    /// A variable that no `def` or `let` binds
+    Unbound(String),
+    /// A `def` anywhere but the top level of a document
+    MisplacedDef,
+    /// A variable in a `script` or `style` element whose value would end it early
+    RawTextEnd(String),
+    /// A variable in a processing instruction's attribute whose value would end it early
+    InvalidProcessingInstruction,
+}
+
+impl EvalError {
+    fn new(source: &str, offset: usize, kind: EvalErrorKind) -> Self {
+        let (line, column) = location(source, offset);
+        Self {
+            offset,
+            line,
+            column,
+            kind,
+        }
+    }
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.kind
+        )
+    }
+}
+
+impl fmt::Display for EvalErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalErrorKind::Unbound(name) => write!(f, "`{}` isn't defined", name),
+            EvalErrorKind::MisplacedDef => {
+                write!(f, "`def` can only be used at the top level of a document")
+            }
+            EvalErrorKind::RawTextEnd(name) => write!(f, "`{0}` can't contain `</{0}`", name),
+            EvalErrorKind::InvalidProcessingInstruction => {
+                write!(f, "processing instructions can't contain `?>`")
+            }
+        }
+    }
+}
+
+impl Document {
+    /// Replace `def`s, `let`s and the variables they bind with what they stand for, leaving a
+    /// document that's ready to render. `source` is what the document was parsed from, to
+    /// locate errors in
+    pub fn evaluate(self, source: &str) -> Result<Document, EvalError> {
+        let mut evaluator = Evaluator {
+            source,
+            bindings: vec![],
+            variable: 0,
+        };
+        let mut nodes = vec![];
+        for node in self.nodes {
+            match node {
+                // Definitions last for the rest of the document
+                Node::Def { name, value, .. } => {
+                    let value = evaluator.resolve(value)?;
+                    evaluator.bindings.push((name, value));
+                }
+                node => evaluator.evaluate(node, &mut nodes, None)?,
+            }
+        }
+        Ok(Document { nodes })
+    }
+}
+
+impl Node {
+    /// Evaluate a single form, like one from [`crate::Parser::parse`], as [`Document::evaluate`]
+    /// would a document of just that form. A `let` stands for its whole body and a `def` for
+    /// nothing, so this gives any number of nodes
+    pub fn evaluate(self, source: &str) -> Result<Vec<Node>, EvalError> {
+        Ok(Document { nodes: vec![self] }.evaluate(source)?.nodes)
+    }
+}
+
+struct Evaluator<'a> {
+    source: &'a str,
+    /// Innermost last, so later bindings shadow earlier ones
+    bindings: Vec<(String, Value)>,
+    /// Where the last variable in child position is. Parsing already rejects text that ends a
+    /// `script` or `style` early on its own, so it's a variable's value that does
+    variable: usize,
+}
+
+impl Evaluator<'_> {
+    /// Evaluate `node`, a child of the element `parent` if it has one, onto the end of `out`. A
+    /// `let` adds its whole body
+    fn evaluate(
+        &mut self,
+        node: Node,
+        out: &mut Vec<Node>,
+        parent: Option<&str>,
+    ) -> Result<(), EvalError> {
+        let node = match node {
+            Node::Variable { name, offset } => {
+                self.variable = offset;
+                // Booleans have no text as attributes, but as text they're written as they're spelt
+                let text = match self.lookup(&name, offset)? {
+                    Value::Bool(b) => b.to_string(),
+                    value => value.as_text().unwrap_or_default(),
+                };
+                Node::Text(text)
+            }
+            Node::Def { offset, .. } => {
+                return Err(self.error_at(offset, EvalErrorKind::MisplacedDef))
+            }
+            Node::Let { bindings, inner } => {
+                let depth = self.bindings.len();
+                // Each binding can use the ones before it
+                for (name, value) in bindings {
+                    let value = self.resolve(value)?;
+                    self.bindings.push((name, value));
+                }
+                for node in inner {
+                    self.evaluate(node, out, parent)?;
+                }
+                self.bindings.truncate(depth);
+                return Ok(());
+            }
+            Node::Tag {
+                name,
+                attributes,
+                inner,
+            } => {
+                let mut evaluated = vec![];
+                for node in inner {
+                    self.evaluate(node, &mut evaluated, Some(&name))?;
+                }
+                Node::Tag {
+                    name,
+                    attributes: self.resolve_attributes(attributes)?,
+                    inner: evaluated,
+                }
+            }
+            Node::ProcessingInstruction { target, attributes } => {
+                // Like a string written there, a value can't end the instruction early
+                for (_, value) in &attributes {
+                    if let Some(offset) = variable_offset(value) {
+                        if ends_processing_instruction(&self.resolve(value.clone())?) {
+                            let kind = EvalErrorKind::InvalidProcessingInstruction;
+                            return Err(self.error_at(offset, kind));
+                        }
+                    }
+                }
+                Node::ProcessingInstruction {
+                    target,
+                    attributes: self.resolve_attributes(attributes)?,
+                }
+            }
+            node => node,
+        };
+        // Like a string written there, a value can't end a `script` or `style` early, even
+        // joined to the text around it
+        if let (Node::Text(text), Some(parent)) = (&node, parent) {
+            if closes_raw_text(parent, &(trailing_text(out) + text)) {
+                let kind = EvalErrorKind::RawTextEnd(parent.to_string());
+                return Err(self.error_at(self.variable, kind));
+            }
+        }
+        out.push(node);
+        Ok(())
+    }
+
+    /// Resolve the attributes' values, merging classes and styles given more than once into the
+    /// first, whatever case they're written in
+    fn resolve_attributes(
+        &self,
+        attributes: Vec<(String, Value)>,
+    ) -> Result<Vec<(String, Value)>, EvalError> {
+        let mut resolved: Vec<(String, Value)> = vec![];
+        for (attr, value) in attributes {
+            let value = self.resolve(value)?;
+            let separator = match attr.to_ascii_lowercase().as_str() {
+                "class" => " ",
+                "style" => "; ",
+                _ => {
+                    resolved.push((attr, value));
+                    continue;
+                }
+            };
+            match resolved
+                .iter_mut()
+                .find(|(existing, _)| existing.eq_ignore_ascii_case(&attr))
+            {
+                Some((_, existing)) => merge(existing, value, separator),
+                None => resolved.push((attr, value)),
+            }
+        }
+        Ok(resolved)
+    }
+
+    /// Replace the variables in `value` with what they're bound to
+    fn resolve(&self, value: Value) -> Result<Value, EvalError> {
+        Ok(match value {
+            Value::Variable { name, offset } => self.lookup(&name, offset)?.clone(),
+            Value::List(list) => Value::List(
+                list.into_iter()
+                    .map(|value| self.resolve(value))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Value::Map(map) => Value::Map(
+                map.into_iter()
+                    .map(|(key, value)| Ok((key, self.resolve(value)?)))
+                    .collect::<Result<_, _>>()?,
+            ),
+            value => value,
+        })
+    }
+
+    /// The value bound to `name`, or an error for the variable at `offset` if nothing is
+    fn lookup(&self, name: &str, offset: usize) -> Result<&Value, EvalError> {
+        self.bindings
+            .iter()
+            .rev()
+            .find(|(bound, _)| bound == name)
+            .map(|(_, value)| value)
+            .ok_or_else(|| self.error_at(offset, EvalErrorKind::Unbound(name.to_string())))
+    }
+
+    fn error_at(&self, offset: usize, kind: EvalErrorKind) -> EvalError {
+        EvalError::new(self.source, offset, kind)
+    }
+}
+
+/// Where the first variable in `value` is, if it has any
+fn variable_offset(value: &Value) -> Option<usize> {
+    match value {
+        Value::Variable { offset, .. } => Some(*offset),
+        Value::List(list) => list.iter().find_map(variable_offset),
+        Value::Map(map) => map.iter().find_map(|(_, value)| variable_offset(value)),
+        _ => None,
+    }
+}
+
+/// The text at the end of `nodes`, which will be written out as one with whatever text follows
+fn trailing_text(nodes: &[Node]) -> String {
+    let mut parts: Vec<&str> = nodes
+        .iter()
+        .rev()
+        .map_while(|node| match node {
+            Node::Text(text) => Some(text.as_str()),
+            _ => None,
+        })
+        .collect();
+    parts.reverse();
+    parts.concat()
+}
+
+/// Join `value` onto `existing`, dropping empty parts and any `;` already ending them
+fn merge(existing: &mut Value, value: Value, separator: &str) {
+    let parts: Vec<String> = [&*existing, &value]
+        .iter()
+        .filter_map(|value| value.as_text())
+        .map(|text| text.trim().trim_end_matches(';').trim_end().to_string())
+        .filter(|text| !text.is_empty())
+        .collect();
+    *existing = if parts.is_empty() {
+        value
+    } else {
+        Value::String(parts.join(separator))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{compile, Error, EvalErrorKind};
+
+    fn error(source: &str) -> (usize, usize, EvalErrorKind) {
+        match compile(source, Default::default()).unwrap_err() {
+            Error::Eval(err) => (err.line, err.column, err.kind),
+            err => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn def_and_let() {
+        let test = r#"(def site "Example") (def cdn "https://cdn.example.com") (def accent "red")
+(html
+    (head (title site) (link :rel stylesheet :href $cdn))
+    (let ((name "World") (greeting $name))
+        (p :style (:color $accent) "Hello " greeting)
+        (let ((name "again")) (p $name)))
+    (p :class ("btn" $accent) :type text))"#;
+        assert_eq!(
+            compile(test, Default::default()).unwrap(),
+            r#"<html><head><title>Example</title><link rel="stylesheet" href="https://cdn.example.com"></head><p style="color: red">Hello World</p><p>again</p><p class="btn red" type="text"></p></html>"#
+        );
+    }
+
+    #[test]
+    fn bare_words_in_attributes_are_not_variables() {
+        let test = r#"(def text "x") (def type "y") (label (input :type text :value $text) text)"#;
+        assert_eq!(
+            compile(test, Default::default()).unwrap(),
+            r#"<label><input type="text" value="x">x</label>"#
+        );
+    }
+
+    #[test]
+    fn booleans_as_text() {
+        let test = r#"(def on true) (let ((off false)) (input :checked $on :disabled $off) (p on " " off))"#;
+        assert_eq!(
+            compile(test, Default::default()).unwrap(),
+            r#"<input checked><p>true false</p>"#
+        );
+    }
+
+    #[test]
+    fn merged_attributes() {
+        let test = r#"(def big "large") (p.a :class $big :class ("b" $big) :style (:color "red") :style "margin: 0")"#;
+        assert_eq!(
+            compile(test, Default::default()).unwrap(),
+            r#"<p class="a large b large" style="color: red; margin: 0"></p>"#
+        );
+
+        let test = r#"(p.a :CLASS "b" :Style "color: red" :style "margin: 0")"#;
+        assert_eq!(
+            compile(test, Default::default()).unwrap(),
+            r#"<p class="a b" Style="color: red; margin: 0"></p>"#
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            error("(p title)\n(def title \"x\")"),
+            (1, 4, EvalErrorKind::Unbound("title".to_string()))
+        );
+        assert_eq!(
+            error("(let ((x \"a\")) (p $x))\n(p $x)"),
+            (2, 4, EvalErrorKind::Unbound("x".to_string()))
+        );
+        assert_eq!(
+            error("(a :href $url)"),
+            (1, 10, EvalErrorKind::Unbound("url".to_string()))
+        );
+        assert_eq!(
+            error("(p :style (:color \"red\" :width $w))"),
+            (1, 32, EvalErrorKind::Unbound("w".to_string()))
+        );
+        assert_eq!(
+            error("(def x (\"a\" $y))"),
+            (1, 13, EvalErrorKind::Unbound("y".to_string()))
+        );
+        assert_eq!(
+            error("(div\n  (def x \"a\"))"),
+            (2, 3, EvalErrorKind::MisplacedDef)
+        );
+        assert_eq!(
+            error("(def x \"</script>\") (p $x) (script $x)"),
+            (1, 36, EvalErrorKind::RawTextEnd("script".to_string()))
+        );
+        assert_eq!(
+            error("(def e \"ipt>\") (script \"</scr\" e)"),
+            (1, 32, EvalErrorKind::RawTextEnd("script".to_string()))
+        );
+        assert_eq!(
+            error("(def s \"</sty\") (style $s (let () \"le>\"))"),
+            (1, 24, EvalErrorKind::RawTextEnd("style".to_string()))
+        );
+        assert_eq!(
+            error("(def end \"?>\") (?xml :a (\"x\" $end))"),
+            (1, 30, EvalErrorKind::InvalidProcessingInstruction)
+        );
+    }
+}
@@ -0,0 +1,540 @@
+use crate::lisp::{INDENT, MAX_WIDTH};
+use crate::parser::{is_attr_name_char, is_symbol_char, ParseError, Parser};
+
+/// A piece of HTMLisp source. Everything is kept as written, so reformatting only ever changes
+/// whitespace
+enum Item {
+    /// A form, or a list or map value
+    List(Vec<Spaced>),
+    /// Names, `:attributes`, bare values and `(!doctype` arguments
+    Atom(String),
+    /// A string literal, escapes and all
+    Str(String),
+    LineComment(String),
+    BlockComment(String),
+    /// `#_` and everything it discards, including any comments in between
+    Discard(Vec<Item>),
+}
+
+/// An item and the whitespace before it in the source
+struct Spaced {
+    item: Item,
+    /// Whether the item started a new line, so a comment that didn't stays trailing
+    new_line: bool,
+    /// Whether there was at least one blank line before the item
+    blank_line: bool,
+}
+
+/// Reprint HTMLisp source in the canonical style, keeping comments and blank lines between forms
+pub fn format(source: &str) -> Result<String, ParseError> {
+    // Only well-formed source is reformatted, which also gives errors their locations
+    Parser::new(source).parse_document()?;
+
+    let items = Reader { source, pos: 0 }.read_items();
+    let mut out = String::new();
+    write_body(&mut out, &items, 0);
+    if !items.is_empty() {
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+struct Reader<'a> {
+    source: &'a str,
+    pos: usize,
+}
+
+impl Reader<'_> {
+    /// Read items up to the end of the source or of the current list, consuming the `)`
+    fn read_items(&mut self) -> Vec<Spaced> {
+        let mut items = vec![];
+        loop {
+            let newlines = self.skip_whitespace();
+            match self.peek() {
+                None => return items,
+                Some(')') => {
+                    self.pos += 1;
+                    return items;
+                }
+                Some(_) => items.push(Spaced {
+                    item: self.read_item(),
+                    new_line: newlines > 0,
+                    blank_line: newlines > 1,
+                }),
+            }
+        }
+    }
+
+    fn read_item(&mut self) -> Item {
+        let rest = &self.source[self.pos..];
+        if rest.starts_with('(') {
+            self.pos += 1;
+            Item::List(self.read_items())
+        } else if let Some(string) = rest.strip_prefix('"') {
+            let mut escaped = false;
+            let end = string
+                .find(|c| {
+                    let end = c == '"' && !escaped;
+                    escaped = c == '\\' && !escaped;
+                    end
+                })
+                .map_or(rest.len(), |i| i + 2);
+            Item::Str(self.take(end))
+        } else if rest.starts_with(';') {
+            let end = rest.find('\n').unwrap_or(rest.len());
+            Item::LineComment(self.take(end).trim_end().to_string())
+        } else if rest.starts_with("#|") {
+            Item::BlockComment(self.take(block_comment_len(rest)))
+        } else if rest.starts_with("#_") {
+            self.pos += 2;
+            self.read_discarded()
+        } else {
+            let name_len = if let Some(attr) = rest.strip_prefix(':') {
+                1 + attr.find(|c| !is_attr_name_char(c)).unwrap_or(attr.len())
+            } else {
+                // Symbols take in tag names and `(!doctype` arguments too
+                rest.find(|c| !is_symbol_char(c)).unwrap_or(rest.len())
+            };
+            // Anything else would have been rejected by the parser, but always make progress
+            let len = match name_len {
+                0 => rest.chars().next().map_or(0, char::len_utf8),
+                len => len,
+            };
+            Item::Atom(self.take(len))
+        }
+    }
+
+    /// `#_` discards the next form, or an attribute and its value
+    fn read_discarded(&mut self) -> Item {
+        let mut discarded = vec![];
+        loop {
+            self.skip_whitespace();
+            if self.peek().is_none() {
+                return Item::Discard(discarded);
+            }
+            let item = self.read_item();
+            match &item {
+                Item::LineComment(_) | Item::BlockComment(_) | Item::Discard(_) => {
+                    discarded.push(item)
+                }
+                Item::Atom(attr) if attr.starts_with(':') => {
+                    discarded.push(item);
+                    self.skip_whitespace();
+                    let rest = &self.source[self.pos..];
+                    if rest.starts_with(starts_value) || starts_list_value(rest) {
+                        discarded.push(self.read_item());
+                    }
+                    return Item::Discard(discarded);
+                }
+                _ => {
+                    discarded.push(item);
+                    return Item::Discard(discarded);
+                }
+            }
+        }
+    }
+
+    /// Skip whitespace, returning how many newlines were in it
+    fn skip_whitespace(&mut self) -> usize {
+        let rest = &self.source[self.pos..];
+        let len = rest
+            .find(|c: char| !c.is_whitespace())
+            .unwrap_or(rest.len());
+        self.pos += len;
+        rest[..len].matches('\n').count()
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.source[self.pos..].chars().next()
+    }
+
+    fn take(&mut self, len: usize) -> String {
+        let taken = &self.source[self.pos..self.pos + len];
+        self.pos += len;
+        taken.to_string()
+    }
+}
+
+/// Length of the nested block comment at the start of `rest`
+fn block_comment_len(rest: &str) -> usize {
+    let mut depth = 0;
+    let mut i = 0;
+    while i < rest.len() {
+        if rest[i..].starts_with("#|") {
+            depth += 1;
+            i += 2;
+        } else if rest[i..].starts_with("|#") {
+            depth -= 1;
+            i += 2;
+            if depth == 0 {
+                return i;
+            }
+        } else {
+            i += rest[i..].chars().next().map_or(1, char::len_utf8);
+        }
+    }
+    rest.len()
+}
+
+/// Whether an attribute's value could start with `c`, rather than the attribute having none
+fn starts_value(c: char) -> bool {
+    c == '"' || c.is_alphanumeric() || matches!(c, '-' | '+' | '.' | '_' | '$')
+}
+
+/// Whether `rest` starts with a list or map value rather than a child element
+fn starts_list_value(rest: &str) -> bool {
+    rest.strip_prefix('(')
+        .is_some_and(|list| list.trim_start().starts_with(['"', ':', '$', ')']))
+}
+
+fn is_value(item: &Item) -> bool {
+    match item {
+        Item::Str(_) => true,
+        Item::Atom(atom) => atom.starts_with(starts_value),
+        Item::List(items) => is_list_value(items),
+        _ => false,
+    }
+}
+
+/// Whether a list's items make it a list or map value rather than a form
+fn is_list_value(items: &[Spaced]) -> bool {
+    items.first().is_none_or(|first| match &first.item {
+        Item::Str(_) => true,
+        Item::Atom(atom) => atom.starts_with([':', '$']),
+        _ => false,
+    })
+}
+
+fn is_attribute(item: &Item) -> bool {
+    match item {
+        Item::Atom(atom) => atom.starts_with(':'),
+        Item::Discard(discarded) => discarded.iter().any(is_attribute),
+        _ => false,
+    }
+}
+
+/// Write items one per line, keeping comments that were on the end of a line there
+fn write_body(out: &mut String, items: &[Spaced], depth: usize) {
+    for (i, spaced) in items.iter().enumerate() {
+        if i > 0 {
+            if let (Item::LineComment(_), false) = (&spaced.item, spaced.new_line) {
+                out.push(' ');
+                write_item(out, &spaced.item, depth);
+                continue;
+            }
+            if spaced.blank_line {
+                out.push('\n');
+            }
+            new_line(out, depth);
+        }
+        write_item(out, &spaced.item, depth);
+    }
+}
+
+fn write_item(out: &mut String, item: &Item, depth: usize) {
+    match item {
+        Item::List(items) => write_list(out, items, depth),
+        Item::Atom(s) | Item::Str(s) | Item::LineComment(s) | Item::BlockComment(s) => {
+            out.push_str(s)
+        }
+        Item::Discard(discarded) => {
+            out.push_str("#_");
+            for (i, item) in discarded.iter().enumerate() {
+                match (i, discarded.get(i.wrapping_sub(1))) {
+                    (_, Some(Item::LineComment(_))) => new_line(out, depth),
+                    (0, _) => {}
+                    _ => out.push(' '),
+                }
+                write_item(out, item, depth);
+            }
+        }
+    }
+}
+
+/// Write a list on one line if it fits, or else like `Node::to_htmlisp`: the name and attributes
+/// first, wrapping the attributes if they don't fit, then each child on its own line
+fn write_list(out: &mut String, items: &[Spaced], depth: usize) {
+    if !items.iter().any(|spaced| spaced.blank_line) {
+        if let Some(flat) = flat_list(items) {
+            if INDENT.len() * depth + flat.chars().count() <= MAX_WIDTH {
+                out.push_str(&flat);
+                return;
+            }
+        }
+    }
+
+    // The name, then attributes and their values, up to the first child or comment. Maps have
+    // no name, just attributes, and lists are all values
+    let mut start = match items.first().map(|spaced| &spaced.item) {
+        Some(first) if is_attribute(first) => 0,
+        _ if is_list_value(items) => items.len(),
+        // `let` keeps its bindings next to it, and `def` its name
+        Some(Item::Atom(name)) if name == "let" || name == "def" => 2,
+        _ => 1,
+    };
+    while let Some(spaced) = items.get(start) {
+        if !is_attribute(&spaced.item) {
+            break;
+        }
+        start += 1;
+        if let (Item::Atom(_), Some(value)) = (&spaced.item, items.get(start)) {
+            if is_value(&value.item) {
+                start += 1;
+            }
+        }
+    }
+    let start = start.min(items.len());
+    let head = &items[..start];
+
+    let flat_head = head
+        .iter()
+        .map(|spaced| flat_item(&spaced.item))
+        .collect::<Option<Vec<_>>>()
+        .map(|flat| flat.join(" "));
+    let attributes = head.iter().filter(|s| is_attribute(&s.item));
+    let wrap_attributes = attributes.count() > 1
+        && flat_head.is_none_or(|flat| INDENT.len() * depth + flat.chars().count() + 1 > MAX_WIDTH);
+
+    out.push('(');
+    for (i, spaced) in head.iter().enumerate() {
+        match i {
+            0 => {}
+            // Whatever follows a line comment has to go on the next line
+            _ if matches!(head[i - 1].item, Item::LineComment(_)) => new_line(out, depth + 1),
+            _ if wrap_attributes && is_attribute(&spaced.item) => new_line(out, depth + 1),
+            _ => out.push(' '),
+        }
+        write_item(out, &spaced.item, depth + 1);
+    }
+    for (i, spaced) in items.iter().enumerate().skip(start) {
+        // An attribute after a comment still keeps its value next to it
+        let value_of = match &items[i - 1].item {
+            Item::Atom(attr) if attr.starts_with(':') => is_value(&spaced.item),
+            _ => false,
+        };
+        if value_of {
+            out.push(' ');
+        } else if let (Item::LineComment(_), false) = (&spaced.item, spaced.new_line) {
+            out.push(' ');
+        } else {
+            if spaced.blank_line {
+                out.push('\n');
+            }
+            new_line(out, depth + 1);
+        }
+        write_item(out, &spaced.item, depth + 1);
+    }
+    if let Some(Item::LineComment(_)) = items.last().map(|spaced| &spaced.item) {
+        new_line(out, depth);
+    }
+    out.push(')');
+}
+
+/// The list on one line, unless something in it has to end a line
+fn flat_list(items: &[Spaced]) -> Option<String> {
+    let items = items
+        .iter()
+        .map(|spaced| flat_item(&spaced.item))
+        .collect::<Option<Vec<_>>>()?;
+    Some(format!("({})", items.join(" ")))
+}
+
+fn flat_item(item: &Item) -> Option<String> {
+    match item {
+        Item::List(items) => flat_list(items),
+        Item::LineComment(_) => None,
+        Item::Atom(s) | Item::Str(s) | Item::BlockComment(s) if !s.contains('\n') => {
+            Some(s.clone())
+        }
+        Item::Atom(_) | Item::Str(_) | Item::BlockComment(_) => None,
+        Item::Discard(discarded) => {
+            let discarded = discarded
+                .iter()
+                .map(flat_item)
+                .collect::<Option<Vec<_>>>()?;
+            Some(format!("#_{}", discarded.join(" ")))
+        }
+    }
+}
+
+fn new_line(out: &mut String, depth: usize) {
+    out.push('\n');
+    for _ in 0..depth {
+        out.push_str(INDENT);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile;
+
+    /// Format `source`, checking formatting is stable and doesn't change the compiled HTML
+    fn check(source: &str) -> String {
+        let formatted = format(source).unwrap();
+        assert_eq!(format(&formatted).unwrap(), formatted);
+        assert_eq!(
+            compile(&formatted, Default::default()).unwrap(),
+            compile(source, Default::default()).unwrap()
+        );
+        formatted
+    }
+
+    #[test]
+    fn layout() {
+        let source = r#"(html   :lang "en"
+  (head (meta :charset "UTF-8")
+ (title "Hi"))
+(body (p "A paragraph which is quite long, long enough that it can't possibly fit on a line with the rest")
+ (a :href "https://example.com/a/very/long/path/that/goes/on/and/on" :title "A long title here too" "link")))"#;
+        assert_eq!(
+            check(source),
+            r#"(html :lang "en"
+    (head (meta :charset "UTF-8") (title "Hi"))
+    (body
+        (p
+            "A paragraph which is quite long, long enough that it can't possibly fit on a line with the rest")
+        (a
+            :href "https://example.com/a/very/long/path/that/goes/on/and/on"
+            :title "A long title here too"
+            "link")))
+"#
+        );
+    }
+
+    #[test]
+    fn matches_converted_html() {
+        let source = r#"(!doctype html) (html (head (title "A page with a title long enough to need wrapping")) (body (p :class "intro" "Hello " (b "World"))))"#;
+        let document = Parser::new(source).parse_document().unwrap();
+        assert_eq!(check(source), document.to_htmlisp());
+    }
+
+    #[test]
+    fn comments_and_blank_lines() {
+        let source = r#"; A page
+(div    ; trailing
+  #| block
+     comment |#
+
+
+  (p ; why
+    "text") #_ (p "gone")
+  (p :class "a" #_ :id "b" "c")
+  (span "last") ; end
+)
+
+
+(p)"#;
+        assert_eq!(
+            check(source),
+            r#"; A page
+(div ; trailing
+    #| block
+     comment |#
+
+    (p ; why
+        "text")
+    #_(p "gone")
+    (p :class "a" #_:id "b" "c")
+    (span "last") ; end
+)
+
+(p)
+"#
+        );
+    }
+
+    #[test]
+    fn literals_are_kept() {
+        let source = "(!DOCTYPE html)(pre \"a\\n\\u{41}\" \"two\nlines\")";
+        assert_eq!(
+            check(source),
+            "(!DOCTYPE html)\n(pre\n    \"a\\n\\u{41}\"\n    \"two\nlines\")\n"
+        );
+    }
+
+    #[test]
+    fn attribute_values() {
+        let long = "x".repeat(60);
+        let source = format!(
+            "(link :rel stylesheet :type text/css :href \"{}\" :disabled #_ :media print :sizes 16)",
+            long
+        );
+        assert_eq!(
+            check(&source),
+            format!(
+                "(link\n    :rel stylesheet\n    :type text/css\n    :href \"{}\"\n    :disabled\n    #_:media print\n    :sizes 16)\n",
+                long
+            )
+        );
+    }
+
+    #[test]
+    fn list_and_map_values() {
+        let long = "x".repeat(40);
+        let source = format!(
+            "(div :class (\"card\"   \"wide\") #_ :style (:color red) :style (:background \"{}\" :border \"{}\") (p))",
+            long, long
+        );
+        assert_eq!(
+            check(&source),
+            format!(
+                "(div\n    :class (\"card\" \"wide\")\n    #_:style (:color red)\n    :style (:background \"{}\"\n        :border \"{}\")\n    (p))\n",
+                long, long
+            )
+        );
+
+        // A comment inside a list still ends its line
+        let source = "(p :class (\"a\" ; c\n \"b\") \"x\")\n(def x (\"a\" ; c\n \"b\"))";
+        assert_eq!(
+            check(source),
+            "(p :class (\"a\" ; c\n        \"b\")\n    \"x\")\n(def x\n    (\"a\" ; c\n        \"b\"))\n"
+        );
+    }
+
+    #[test]
+    fn def_and_let() {
+        let source = r#"(def   title "Home")
+(let ((greeting "Hello") (name "World")) (p :class "greeting" $greeting " " $name) (p :class "title" $title))"#;
+        assert_eq!(
+            check(source),
+            r#"(def title "Home")
+(let ((greeting "Hello") (name "World"))
+    (p :class "greeting" $greeting " " $name)
+    (p :class "title" $title))
+"#
+        );
+    }
+
+    #[test]
+    fn def_keeps_its_name() {
+        let long = "x".repeat(90);
+        let source = format!(r#"(def classes ("{}" "b"))"#, long);
+        assert_eq!(
+            check(&source),
+            format!("(def classes\n    (\"{}\" \"b\"))\n", long)
+        );
+    }
+
+    #[test]
+    fn attributes_after_comments() {
+        let source = r#"(p :a "x" ; c
+  :b "y" (span)) (p :style (:color "red" ; c
+ :margin 0))"#;
+        assert_eq!(
+            check(source),
+            r#"(p :a "x" ; c
+    :b "y"
+    (span))
+(p :style (:color "red" ; c
+        :margin 0))
+"#
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(format("(p").unwrap_err().offset, 2);
+        assert_eq!(format("").unwrap(), "");
+    }
+}
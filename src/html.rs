@@ -0,0 +1,699 @@
+use crate::entities::{ENTITIES, MAX_ENTITY_LEN};
+use crate::parser::{
+    is_attr_name_char, is_tag_name_char, Document, Node, ParseError, ParseErrorKind, Value,
+    MAX_DEPTH,
+};
+use crate::render::{
+    drops_leading_newline, is_block_element, is_preformatted_element, is_raw_text_element,
+    is_void_element,
+};
+
+/// Reads HTML into the same tree HTMLisp parses to. Like a browser it doesn't reject malformed
+/// markup: missing end tags are implied and stray ones are ignored
+pub struct HtmlParser<'input> {
+    source: &'input str,
+    pos: usize,
+}
+
+/// An element that's been started but not ended yet
+struct Open {
+    name: String,
+    attributes: Vec<(String, Value)>,
+    inner: Vec<Node>,
+}
+
+impl<'input> HtmlParser<'input> {
+    pub fn new(input: &'input str) -> Self {
+        Self {
+            source: input,
+            pos: 0,
+        }
+    }
+
+    /// Only fails on names that can't be written in HTMLisp, like Angular's `(click)`
+    pub fn parse_document(&mut self) -> Result<Document, ParseError> {
+        let mut stack = vec![Open {
+            name: String::new(),
+            attributes: vec![],
+            inner: vec![],
+        }];
+
+        while self.pos < self.source.len() {
+            let rest = self.rest();
+            if let Some(body) = rest.strip_prefix("<!--") {
+                // Browsers end comments at `--!>` too, if it comes first
+                let end = match (body.find("-->"), body.find("--!>")) {
+                    (Some(end), Some(bang)) if bang > end => "-->",
+                    (_, Some(_)) => "--!>",
+                    _ => "-->",
+                };
+                let comment = self.take_until(end, 4);
+                let comment = comment.strip_prefix(' ').unwrap_or(comment);
+                let comment = comment.strip_suffix(' ').unwrap_or(comment);
+                push_node(&mut stack, Node::Comment(comment.to_string()));
+            } else if rest.starts_with("<![CDATA[") {
+                let text = self.take_until("]]>", 9);
+                push_node(&mut stack, Node::Text(text.to_string()));
+            } else if rest.starts_with("<!") {
+                let declaration = self.take_until(">", 2);
+                // Like browsers, ignore doctypes inside elements, which HTMLisp can't have
+                if starts_with_ignore_case(declaration, "doctype") && stack.len() == 1 {
+                    let doctype = declaration[7..].trim();
+                    push_node(&mut stack, Node::Doctype(doctype.to_string()));
+                }
+            } else if rest.starts_with("<?") {
+                let offset = self.pos + 2;
+                let instruction = self.take_until(">", 2);
+                let instruction = instruction.strip_suffix('?').unwrap_or(instruction);
+                let mut parser = HtmlParser {
+                    source: &self.source[..offset + instruction.len()],
+                    pos: offset,
+                };
+                let target = parser.parse_tag_name()?;
+                let attributes = parser.parse_attributes()?;
+                push_node(
+                    &mut stack,
+                    Node::ProcessingInstruction { target, attributes },
+                );
+            } else if rest.starts_with("</")
+                && rest[2..].starts_with(|c: char| c.is_ascii_alphabetic())
+            {
+                self.pos += 2;
+                let name = self.take_while(|c| !c.is_ascii_whitespace() && c != '>');
+                self.take_until(">", 0);
+                // End tags without a matching start tag are ignored
+                if let Some(i) = stack
+                    .iter()
+                    .rposition(|open| open.name.eq_ignore_ascii_case(name))
+                {
+                    while stack.len() > i {
+                        close(&mut stack);
+                    }
+                }
+            } else if rest.starts_with('<')
+                && rest[1..].starts_with(|c: char| c.is_ascii_alphabetic())
+            {
+                self.parse_element(&mut stack)?;
+            } else {
+                // A `<` that doesn't start a tag is just text
+                let first = rest.chars().next().map_or(1, char::len_utf8);
+                let end = rest[first..].find('<').map_or(rest.len(), |i| i + first);
+                let text = decode_entities(&rest[..end], false);
+                self.pos += end;
+                push_node(&mut stack, Node::Text(text));
+            }
+        }
+
+        while stack.len() > 1 {
+            close(&mut stack);
+        }
+        let mut nodes = stack.pop().map(|root| root.inner).unwrap_or_default();
+        tidy_whitespace(&mut nodes, true);
+        Ok(Document { nodes })
+    }
+
+    fn parse_element(&mut self, stack: &mut Vec<Open>) -> Result<(), ParseError> {
+        let start = self.pos;
+        self.pos += 1;
+        let offset = self.pos;
+        let name = self.parse_tag_name()?;
+        // `(def ...)` and `(let ...)` are special forms rather than elements
+        if name == "def" || name == "let" {
+            return Err(self.unsupported_name(offset, &name));
+        }
+        let attributes = self.parse_attributes()?;
+        let self_closing = self.rest().starts_with('/');
+        self.take_until(">", 0);
+
+        while stack
+            .last()
+            .is_some_and(|open| implicitly_closes(&name, &open.name))
+        {
+            close(stack);
+        }
+        // Past the depth `Parser` takes the converted file couldn't be compiled, and the tree
+        // couldn't be walked without overflowing the stack. The bottom of the stack is the
+        // document rather than an element
+        if stack.len() > MAX_DEPTH {
+            return Err(ParseError::new(self.source, start, ParseErrorKind::TooDeep));
+        }
+
+        if is_void_element(&name) || self_closing {
+            push_node(
+                stack,
+                Node::Tag {
+                    name,
+                    attributes,
+                    inner: vec![],
+                },
+            );
+        } else if is_raw_text_element(&name) || is_escapable_raw_text_element(&name) {
+            // Everything up to the end tag is text, tags and all
+            let end =
+                find_ignore_case(self.rest(), &format!("</{}", name)).unwrap_or(self.rest().len());
+            let text = &self.rest()[..end];
+            let text = if is_raw_text_element(&name) {
+                text.to_string()
+            } else if drops_leading_newline(&name) {
+                decode_entities(text.strip_prefix('\n').unwrap_or(text), false)
+            } else {
+                decode_entities(text, false)
+            };
+            self.pos += end;
+            self.take_until(">", 0);
+
+            let inner = if text.is_empty() {
+                vec![]
+            } else {
+                vec![Node::Text(text)]
+            };
+            push_node(
+                stack,
+                Node::Tag {
+                    name,
+                    attributes,
+                    inner,
+                },
+            );
+        } else {
+            if drops_leading_newline(&name) && self.rest().starts_with('\n') {
+                self.pos += 1;
+            }
+            stack.push(Open {
+                name,
+                attributes,
+                inner: vec![],
+            });
+        }
+        Ok(())
+    }
+
+    fn parse_tag_name(&mut self) -> Result<String, ParseError> {
+        let offset = self.pos;
+        let name = self.take_while(|c| !c.is_ascii_whitespace() && c != '/' && c != '>');
+        if !name.starts_with(|c: char| c.is_ascii_alphanumeric())
+            || !name.chars().all(is_tag_name_char)
+        {
+            return Err(self.unsupported_name(offset, name));
+        }
+        Ok(name.to_string())
+    }
+
+    /// Attributes up to, but not including, the `/>` or `>` that ends the start tag
+    fn parse_attributes(&mut self) -> Result<Vec<(String, Value)>, ParseError> {
+        let mut attributes = vec![];
+        loop {
+            self.take_while(|c| c.is_ascii_whitespace() || c == '/');
+            if self.rest().is_empty() || self.rest().starts_with('>') {
+                // Leave a `/` that ends the tag for `parse_element` to see
+                if self.source[..self.pos].ends_with('/') {
+                    self.pos -= 1;
+                }
+                return Ok(attributes);
+            }
+
+            let offset = self.pos;
+            let mut name =
+                self.take_while(|c| !c.is_ascii_whitespace() && !matches!(c, '/' | '>' | '='));
+            if name.is_empty() {
+                // A lone `=`, which browsers treat as the start of a name
+                name = &self.rest()[..1];
+                self.pos += 1;
+            }
+            if !name.chars().all(is_attr_name_char) {
+                return Err(self.unsupported_name(offset, name));
+            }
+            let name = name.to_string();
+
+            self.take_while(|c| c.is_ascii_whitespace());
+            let value = if self.rest().starts_with('=') {
+                self.pos += 1;
+                self.take_while(|c| c.is_ascii_whitespace());
+                Value::String(decode_entities(self.parse_attribute_value(), true))
+            } else {
+                Value::Bool(true)
+            };
+
+            // Like browsers, only the first of a repeated attribute counts
+            if !attributes
+                .iter()
+                .any(|(existing, _): &(String, Value)| existing.eq_ignore_ascii_case(&name))
+            {
+                attributes.push((name, value));
+            }
+        }
+    }
+
+    /// A quoted or unquoted value after the `=`
+    fn parse_attribute_value(&mut self) -> &'input str {
+        match self.rest().chars().next() {
+            Some(quote) if quote == '"' || quote == '\'' => {
+                self.pos += 1;
+                let value = self.take_while(|c| c != quote);
+                self.pos = (self.pos + 1).min(self.source.len());
+                value
+            }
+            _ => self.take_while(|c| !c.is_ascii_whitespace() && c != '>'),
+        }
+    }
+
+    fn rest(&self) -> &'input str {
+        &self.source[self.pos..]
+    }
+
+    fn take_while(&mut self, f: impl Fn(char) -> bool) -> &'input str {
+        let rest = self.rest();
+        let end = rest.find(|c| !f(c)).unwrap_or(rest.len());
+        self.pos += end;
+        &rest[..end]
+    }
+
+    /// Skip `skip` bytes, then take everything up to `end`, consuming `end` too
+    fn take_until(&mut self, end: &str, skip: usize) -> &'input str {
+        let rest = &self.rest()[skip.min(self.rest().len())..];
+        match rest.find(end) {
+            Some(i) => {
+                self.pos = self.source.len() - rest.len() + i + end.len();
+                &rest[..i]
+            }
+            None => {
+                self.pos = self.source.len();
+                rest
+            }
+        }
+    }
+
+    fn unsupported_name(&self, offset: usize, name: &str) -> ParseError {
+        ParseError::new(
+            self.source,
+            offset,
+            ParseErrorKind::UnsupportedName(name.to_string()),
+        )
+    }
+}
+
+fn push_node(stack: &mut [Open], node: Node) {
+    let inner = match stack.last_mut() {
+        Some(open) => &mut open.inner,
+        None => return,
+    };
+    match (inner.last_mut(), node) {
+        (Some(Node::Text(text)), Node::Text(more)) => text.push_str(&more),
+        (_, node) => inner.push(node),
+    }
+}
+
+fn close(stack: &mut Vec<Open>) {
+    if let Some(open) = stack.pop() {
+        push_node(
+            stack,
+            Node::Tag {
+                name: open.name,
+                attributes: open.attributes,
+                inner: open.inner,
+            },
+        );
+    }
+}
+
+/// Whether starting an `opening` element ends an `open` one, like a `<li>` ends the previous `<li>`
+fn implicitly_closes(opening: &str, open: &str) -> bool {
+    let opening = opening.to_ascii_lowercase();
+    match open.to_ascii_lowercase().as_str() {
+        "p" => [
+            "address",
+            "article",
+            "aside",
+            "blockquote",
+            "details",
+            "div",
+            "dl",
+            "fieldset",
+            "figcaption",
+            "figure",
+            "footer",
+            "form",
+            "h1",
+            "h2",
+            "h3",
+            "h4",
+            "h5",
+            "h6",
+            "header",
+            "hgroup",
+            "hr",
+            "main",
+            "menu",
+            "nav",
+            "ol",
+            "p",
+            "pre",
+            "section",
+            "table",
+            "ul",
+        ]
+        .contains(&opening.as_str()),
+        "li" => opening == "li",
+        "dt" | "dd" => opening == "dt" || opening == "dd",
+        "td" | "th" => ["td", "th", "tr", "tbody", "tfoot"].contains(&opening.as_str()),
+        "tr" => ["tr", "tbody", "tfoot"].contains(&opening.as_str()),
+        "thead" | "tbody" => opening == "tbody" || opening == "tfoot",
+        "option" => opening == "option" || opening == "optgroup",
+        "optgroup" => opening == "optgroup",
+        _ => false,
+    }
+}
+
+/// Elements whose content is text, but which unlike `script` can have entities in it
+fn is_escapable_raw_text_element(name: &str) -> bool {
+    name.eq_ignore_ascii_case("textarea") || name.eq_ignore_ascii_case("title")
+}
+
+/// Drop whitespace that can't affect how the page renders, so it doesn't end up as `" "` strings
+/// all over the converted file: runs of whitespace become one space, and whitespace at the edges
+/// of a block or next to a block box goes entirely
+fn tidy_whitespace(nodes: &mut Vec<Node>, in_block: bool) {
+    for i in 0..nodes.len() {
+        let trim_start = drops_whitespace(nodes[..i].iter().rev(), in_block);
+        let trim_end = drops_whitespace(nodes[i + 1..].iter(), in_block);
+        match &mut nodes[i] {
+            Node::Text(text) => {
+                let mut tidy = String::with_capacity(text.len());
+                let mut space = false;
+                for c in text.chars() {
+                    if c.is_ascii_whitespace() {
+                        space = true;
+                        continue;
+                    }
+                    if space && !(tidy.is_empty() && trim_start) {
+                        tidy.push(' ');
+                    }
+                    space = false;
+                    tidy.push(c);
+                }
+                if space && !trim_end && !(tidy.is_empty() && trim_start) {
+                    tidy.push(' ');
+                }
+                *text = tidy;
+            }
+            Node::Tag { name, inner, .. } if !is_preformatted_element(name) => {
+                tidy_whitespace(inner, is_block_element(name));
+            }
+            _ => {}
+        }
+    }
+    nodes.retain(|node| !matches!(node, Node::Text(text) if text.is_empty()));
+}
+
+/// Whether whitespace next to `nodes`, nearest first, isn't rendered because it's next to a block
+/// box or the edge of a block. Comments and elements that aren't rendered don't separate it from
+/// those, but anything else that isn't whitespace does
+fn drops_whitespace<'a>(nodes: impl Iterator<Item = &'a Node>, at_block_edge: bool) -> bool {
+    for node in nodes {
+        match node {
+            Node::Text(text) if text.chars().all(|c| c.is_ascii_whitespace()) => {}
+            Node::Comment(_) | Node::Doctype(_) | Node::ProcessingInstruction { .. } => {}
+            Node::Tag { name, .. } if is_hidden_element(name) => {}
+            Node::Tag { name, .. } => return is_block_element(name),
+            _ => return false,
+        }
+    }
+    at_block_edge
+}
+
+/// Elements that are never rendered, so don't make a box whitespace next to them would be
+/// dropped beside
+fn is_hidden_element(name: &str) -> bool {
+    [
+        "base", "head", "link", "meta", "script", "source", "style", "template", "title",
+    ]
+    .iter()
+    .any(|hidden| hidden.eq_ignore_ascii_case(name))
+}
+
+/// Replace the character references in `s` with what they stand for, leaving anything that only
+/// looks like one as it is
+fn decode_entities(s: &str, in_attribute: bool) -> String {
+    let mut decoded = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(amp) = rest.find('&') {
+        decoded.push_str(&rest[..amp]);
+        rest = &rest[amp + 1..];
+        match decode_entity(rest, in_attribute) {
+            Some((text, len)) => {
+                decoded.push_str(&text);
+                rest = &rest[len..];
+            }
+            None => decoded.push('&'),
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+/// What the character reference at the start of `rest`, just after its `&`, stands for, and how
+/// long it is. Like browsers, this takes the longest name it can, so `&notin;` is `∉` but
+/// `&notit;` is `¬it;`, except in attribute values, where a legacy name with no `;` followed by a
+/// letter, digit or `=` is left alone, as in `?a=1&copy=2`
+fn decode_entity(rest: &str, in_attribute: bool) -> Option<(String, usize)> {
+    if let Some(number) = rest.strip_prefix('#') {
+        let (digits, radix) = match number.strip_prefix(['x', 'X']) {
+            Some(hex) => (hex, 16),
+            None => (number, 10),
+        };
+        let end = digits
+            .find(|c: char| !c.is_digit(radix))
+            .unwrap_or(digits.len());
+        let c = u32::from_str_radix(&digits[..end], radix)
+            .ok()
+            .and_then(char::from_u32)?;
+        let len = rest.len() - digits.len() + end;
+        let len = len + usize::from(rest[len..].starts_with(';'));
+        return Some((c.to_string(), len));
+    }
+    // Names are letters and digits, then a `;` if they have one
+    let name_len = rest
+        .find(|c: char| !c.is_ascii_alphanumeric())
+        .unwrap_or(rest.len());
+    let longest = name_len + usize::from(rest[name_len..].starts_with(';'));
+    let (len, text) = (1..=longest.min(MAX_ENTITY_LEN)).rev().find_map(|len| {
+        ENTITIES
+            .binary_search_by_key(&&rest[..len], |&(name, _)| name)
+            .ok()
+            .map(|i| (len, ENTITIES[i].1))
+    })?;
+    let legacy = !rest[..len].ends_with(';');
+    if legacy
+        && in_attribute
+        && rest[len..].starts_with(|c: char| c.is_ascii_alphanumeric() || c == '=')
+    {
+        return None;
+    }
+    Some((text.to_string(), len))
+}
+
+fn starts_with_ignore_case(s: &str, prefix: &str) -> bool {
+    s.len() >= prefix.len() && s.as_bytes()[..prefix.len()].eq_ignore_ascii_case(prefix.as_bytes())
+}
+
+fn find_ignore_case(haystack: &str, needle: &str) -> Option<usize> {
+    (0..=haystack.len().saturating_sub(needle.len()))
+        .filter(|&i| haystack.is_char_boundary(i))
+        .find(|&i| starts_with_ignore_case(&haystack[i..], needle))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Parser;
+
+    fn convert(html: &str) -> String {
+        HtmlParser::new(html).parse_document().unwrap().to_htmlisp()
+    }
+
+    /// The HTML that the converted HTMLisp compiles back to
+    fn round_trip(html: &str) -> String {
+        Parser::new(&convert(html))
+            .parse_document()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn document() {
+        let html = r#"<!DOCTYPE html>
+<html lang="en">
+  <head>
+    <meta charset="UTF-8">
+    <title>Tom &amp; Jerry</title>
+  </head>
+  <body>
+    <!-- main content -->
+    <h1 class='big'>Hello   <em>World</em>!</h1>
+    <img src=logo.png alt="">
+  </body>
+</html>
+"#;
+        assert_eq!(
+            convert(html),
+            r#"(!doctype html)
+(html :lang "en"
+    (head (meta :charset "UTF-8") (title "Tom & Jerry"))
+    (body
+        (!-- "main content")
+        (h1 :class "big" "Hello " (em "World") "!")
+        (img :src "logo.png" :alt "")))
+"#
+        );
+        assert_eq!(convert("<p><!doctype html>a</p>"), "(p \"a\")\n");
+    }
+
+    #[test]
+    fn whitespace() {
+        assert_eq!(
+            round_trip("<p>\n  one <b>two</b> <i>three</i>\n</p> <span> four </span>"),
+            "<p>one <b>two</b> <i>three</i></p><span> four </span>"
+        );
+        assert_eq!(
+            round_trip("<pre>\n  keep\n    this</pre>"),
+            "<pre>  keep\n    this</pre>"
+        );
+        assert_eq!(
+            convert("<pre>\n\nx</pre><textarea>\n\ny</textarea>"),
+            "(pre \"\\nx\")\n(textarea \"\\ny\")\n"
+        );
+        assert_eq!(
+            round_trip("<pre>\n\nx</pre><textarea>\n\ny</textarea>"),
+            "<pre>\n\nx</pre><textarea>\n\ny</textarea>"
+        );
+    }
+
+    #[test]
+    fn whitespace_next_to_hidden_nodes() {
+        assert_eq!(
+            convert("<p>Hello <script>x()</script> world</p>"),
+            "(p \"Hello \" (script \"x()\") \" world\")\n"
+        );
+        assert_eq!(
+            round_trip("<p>Hello <script>x()</script> world</p>"),
+            "<p>Hello <script>x()</script> world</p>"
+        );
+        assert_eq!(round_trip("<p>a<!-- c --> b</p>"), "<p>a<!-- c --> b</p>");
+        assert_eq!(
+            round_trip("<!-- a --!> b --> <!-- c --> --!>"),
+            "<!-- a -->b --&gt; <!-- c --> --!&gt;"
+        );
+        assert_eq!(
+            round_trip("<div>\n  <!-- a -->\n  <!-- b -->\n  <p>c</p> <link>\n</div>"),
+            "<div><!-- a --><!-- b --><p>c</p><link></div>"
+        );
+    }
+
+    #[test]
+    fn raw_text() {
+        let html =
+            "<script>if (a < b && c) { x = '</div>' }</script><textarea>&lt;b&gt;</textarea>";
+        assert_eq!(round_trip(html), html);
+    }
+
+    #[test]
+    fn entities() {
+        assert_eq!(
+            convert(r#"<p title="&quot;hi&quot;">&copy; 2024 &#8212; &#x41; &bogus; & 1 < 2</p>"#),
+            "(p :title \"\\\"hi\\\"\" \"\u{a9} 2024 \u{2014} A &bogus; & 1 < 2\")\n"
+        );
+        assert_eq!(
+            round_trip("<p>caf&eacute; &NotEqualTilde;</p>"),
+            "<p>caf\u{e9} \u{2242}\u{338}</p>"
+        );
+
+        // Legacy names work without a `;`, except before a letter, digit or `=` in an attribute
+        assert_eq!(
+            convert(r#"<a href="?a=1&copy=2&amp;b&lt" title="&notit; &notin; &#65">&amp &copy2024 &notit;</a>"#),
+            "(a :href \"?a=1&copy=2&b<\" :title \"&notit; \u{2209} A\" \"& \u{a9}2024 \u{ac}it;\")\n"
+        );
+    }
+
+    #[test]
+    fn implied_end_tags() {
+        assert_eq!(
+            round_trip("<ul><li>one<li>two</ul><p>a<p>b<div>c</div></span>"),
+            "<ul><li>one</li><li>two</li></ul><p>a</p><p>b</p><div>c</div>"
+        );
+        assert_eq!(
+            round_trip("<table><tr><td>1<td>2<tr><td>3</table>"),
+            "<table><tr><td>1</td><td>2</td></tr><tr><td>3</td></tr></table>"
+        );
+    }
+
+    #[test]
+    fn self_closing_and_processing_instructions() {
+        assert_eq!(
+            convert(r#"<?xml version="1.0"?><svg viewBox="0 0 1 1"><path d="M0 0"/></svg><br/>"#),
+            "(?xml :version \"1.0\")\n(svg :viewBox \"0 0 1 1\" (path :d \"M0 0\"))\n(br)\n"
+        );
+    }
+
+    #[test]
+    fn valueless_attributes() {
+        let html = r#"<input disabled type=checkbox checked><option value="">"#;
+        assert_eq!(
+            convert(html),
+            "(input :disabled :type \"checkbox\" :checked)\n(option :value \"\")\n"
+        );
+        assert_eq!(
+            round_trip(html),
+            r#"<input disabled type="checkbox" checked><option value=""></option>"#
+        );
+
+        let html = r#"<p hidden>text</p><div hidden lang=en><b>x</b></div><details open>"#;
+        assert_eq!(
+            convert(html),
+            "(p :hidden true \"text\")\n(div :hidden :lang \"en\" (b \"x\"))\n(details :open)\n"
+        );
+        assert_eq!(
+            round_trip(html),
+            r#"<p hidden>text</p><div hidden lang="en"><b>x</b></div><details open></details>"#
+        );
+    }
+
+    #[test]
+    fn duplicate_attributes() {
+        assert_eq!(
+            convert(r#"<p class="a" CLASS="b" id="c" title id="d">"#),
+            "(p :class \"a\" :id \"c\" :title)\n"
+        );
+    }
+
+    #[test]
+    fn deeply_nested() {
+        let html = "<div>".repeat(MAX_DEPTH);
+        let lisp = convert(&html);
+        assert!(Parser::new(&lisp).parse_document().is_ok());
+
+        let html = format!("\n{}", "<div>".repeat(100_000));
+        let err = HtmlParser::new(&html).parse_document().unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::TooDeep);
+        assert_eq!((err.line, err.column), (2, MAX_DEPTH * 5 + 1));
+    }
+
+    #[test]
+    fn unsupported_names() {
+        let err = HtmlParser::new("<p>\n<button (click)=\"go()\">")
+            .parse_document()
+            .unwrap_err();
+        assert_eq!((err.line, err.column), (2, 9));
+        assert_eq!(
+            err.kind,
+            ParseErrorKind::UnsupportedName("(click)".to_string())
+        );
+
+        let err = HtmlParser::new("<div><let>a</let></div>")
+            .parse_document()
+            .unwrap_err();
+        assert_eq!(err.column, 7);
+        assert_eq!(err.kind, ParseErrorKind::UnsupportedName("let".to_string()));
+        assert!(HtmlParser::new("<def>").parse_document().is_err());
+        assert_eq!(convert("<LET>a</LET>"), "(LET \"a\")\n");
+    }
+}
@@ -0,0 +1,244 @@
+//! Compiles HTMLisp, lisp style HTML, into normal HTML
+//!
+//! ```
+//! let html = htmlisp::compile(r#"(p :class "greeting" "Hello World")"#, Default::default());
+//! assert_eq!(html.unwrap(), r#"<p class="greeting">Hello World</p>"#);
+//! ```
+
+mod entities;
+mod eval;
+mod format;
+mod html;
+mod lisp;
+mod parser;
+mod render;
+
+pub use eval::{EvalError, EvalErrorKind};
+pub use html::HtmlParser;
+pub use parser::{Document, Node, ParseError, ParseErrorKind, Parser, Token, Value};
+use render::IoWriter;
+pub use render::{FormatOptions, WrapAttributes};
+use std::{fmt, io};
+
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Put each element on its own, indented line
+    pub prettify: bool,
+    /// How to lay out prettified output
+    pub format: FormatOptions,
+    /// Start the output with `<!DOCTYPE html>` if the document's root is an `html` element and
+    /// it doesn't have a doctype already
+    pub doctype: bool,
+    /// Write valueless attributes as `name=""` rather than just `name`, as XHTML needs
+    pub xhtml: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    Parse(ParseError),
+    Eval(EvalError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Parse(e) => write!(f, "{}", e),
+            Error::Eval(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl std::error::Error for ParseError {}
+
+impl std::error::Error for EvalError {}
+
+impl From<ParseError> for Error {
+    fn from(e: ParseError) -> Self {
+        Error::Parse(e)
+    }
+}
+
+impl From<EvalError> for Error {
+    fn from(e: EvalError) -> Self {
+        Error::Eval(e)
+    }
+}
+
+/// Parse a whole HTMLisp document and render it as HTML
+pub fn compile(input: &str, options: Options) -> Result<String, Error> {
+    let document = parse(input, &options)?;
+
+    let mut html = String::new();
+    // Writing to a `String` can't fail
+    let _ = if options.prettify {
+        document.render_pretty(&mut html, &options.format, 0)
+    } else {
+        document.render(&mut html)
+    };
+    Ok(html)
+}
+
+/// Convert HTML into HTMLisp that compiles back to equivalent HTML
+pub fn html_to_htmlisp(html: &str) -> Result<String, Error> {
+    Ok(HtmlParser::new(html).parse_document()?.to_htmlisp())
+}
+
+/// Reprint HTMLisp source in the canonical style `html_to_htmlisp` also uses, keeping comments
+pub fn format(input: &str) -> Result<String, Error> {
+    Ok(format::format(input)?)
+}
+
+/// Parse and evaluate a whole HTMLisp document, applying `options` that change the document itself
+pub fn parse(input: &str, options: &Options) -> Result<Document, Error> {
+    let document = Parser::new(input).parse_document()?;
+    let mut document = document.evaluate(input)?;
+
+    if options.doctype {
+        let has_doctype = document
+            .nodes
+            .iter()
+            .any(|node| matches!(node, Node::Doctype(_)));
+        let root = document
+            .nodes
+            .iter()
+            .find(|node| matches!(node, Node::Tag { .. }));
+        if let (false, Some(Node::Tag { name, .. })) = (has_doctype, root) {
+            if name.eq_ignore_ascii_case("html") {
+                document.nodes.insert(0, Node::Doctype("html".to_string()));
+            }
+        }
+    }
+
+    if options.xhtml {
+        add_empty_values(&mut document.nodes);
+    }
+
+    Ok(document)
+}
+
+/// Give `true` attributes an explicit empty value
+fn add_empty_values(nodes: &mut [Node]) {
+    for node in nodes {
+        let attributes = match node {
+            Node::Tag {
+                attributes, inner, ..
+            } => {
+                add_empty_values(inner);
+                attributes
+            }
+            Node::ProcessingInstruction { attributes, .. } => attributes,
+            _ => continue,
+        };
+        for (_, value) in attributes.iter_mut() {
+            if *value == Value::Bool(true) {
+                *value = Value::String(String::new());
+            }
+        }
+    }
+}
+
+/// Write a parsed document to `out` as HTML as it's rendered, without building it up in memory
+/// first. `out` isn't buffered here, so wrap it in a `BufWriter` if writes are expensive
+pub fn render<W: io::Write>(document: &Document, options: &Options, out: W) -> io::Result<()> {
+    let mut out = IoWriter {
+        inner: out,
+        error: None,
+    };
+    let result = if options.prettify {
+        document.render_pretty(&mut out, &options.format, 0)
+    } else {
+        document.render(&mut out)
+    };
+
+    match (result, out.error) {
+        (Ok(()), _) => Ok(()),
+        (Err(_), Some(e)) => Err(e),
+        (Err(_), None) => Err(io::Error::other(
+            "failed to render HTML, the document has to be evaluated first",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auto_doctype() {
+        let options = Options {
+            doctype: true,
+            ..Default::default()
+        };
+        let compiled = compile("(!-- \"page\") (html)", options.clone()).unwrap();
+        assert_eq!(compiled, "<!DOCTYPE html><!-- page --><html></html>");
+
+        let compiled = compile("(!doctype html) (html)", options.clone()).unwrap();
+        assert_eq!(compiled, "<!DOCTYPE html><html></html>");
+
+        let compiled = compile("(div)", options).unwrap();
+        assert_eq!(compiled, "<div></div>");
+    }
+
+    #[test]
+    fn xhtml_attributes() {
+        let test = r#"(input :checked :type "checkbox" :disabled)"#;
+        assert_eq!(
+            compile(test, Default::default()).unwrap(),
+            r#"<input checked type="checkbox" disabled>"#
+        );
+        let options = Options {
+            xhtml: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            compile(test, options).unwrap(),
+            r#"<input checked="" type="checkbox" disabled="">"#
+        );
+    }
+
+    #[test]
+    fn render_to_writer() {
+        let options = Options {
+            prettify: true,
+            doctype: true,
+            ..Default::default()
+        };
+        let document = parse(r#"(html (body (p "a < b")))"#, &options).unwrap();
+        let mut html = vec![];
+        render(&document, &options, &mut html).unwrap();
+        assert_eq!(
+            String::from_utf8(html).unwrap(),
+            compile(r#"(html (body (p "a < b")))"#, options).unwrap()
+        );
+    }
+
+    #[test]
+    fn evaluate_before_rendering() {
+        let source = r#"(def name "World") (p "Hello " $name)"#;
+        let document = Parser::new(source).parse_document().unwrap();
+        let mut html = String::new();
+        assert!(document.render(&mut html).is_err());
+        assert!(render(&document, &Options::default(), vec![]).is_err());
+
+        let document = document.evaluate(source).unwrap();
+        assert_eq!(document.to_string(), "<p>Hello World</p>");
+
+        // A list with a variable in it has no text until it's evaluated either
+        let node = Parser::new(r#"(p :class ("b" $x))"#).parse().unwrap();
+        assert!(node.pretty_print(0).is_err());
+
+        let source = r#"(let ((x "a")) (p :class ("b" $x)) (p $x))"#;
+        let node = Parser::new(source).parse().unwrap();
+        assert!(node.pretty_print(0).is_err());
+
+        let html: Vec<String> = node
+            .evaluate(source)
+            .unwrap()
+            .iter()
+            .map(Node::to_string)
+            .collect();
+        assert_eq!(html, [r#"<p class="b a"></p>"#, "<p>a</p>"]);
+    }
+}
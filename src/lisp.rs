@@ -0,0 +1,387 @@
+use crate::parser::{is_tag_name_char, Document, Node, Value};
+
+/// Converted files are indented like the examples, four spaces a level
+pub(crate) const INDENT: &str = "    ";
+/// Forms are kept on one line if they fit in this many columns
+pub(crate) const MAX_WIDTH: usize = 100;
+
+impl Document {
+    /// Write the tree out as HTMLisp, one top-level form per line
+    pub fn to_htmlisp(&self) -> String {
+        let mut lisp = String::new();
+        for node in &self.nodes {
+            write_form(&mut lisp, node, 0);
+            lisp.push('\n');
+        }
+        lisp
+    }
+}
+
+impl Node {
+    /// Write the node out as HTMLisp, which parses back to the same node
+    pub fn to_htmlisp(&self) -> String {
+        let mut lisp = String::new();
+        write_form(&mut lisp, self, 0);
+        lisp
+    }
+}
+
+/// Write an element on one line if it fits, or else with each child on its own line
+fn write_form(out: &mut String, node: &Node, depth: usize) {
+    let (name, attributes, inner) = match node {
+        Node::Tag {
+            name,
+            attributes,
+            inner,
+        } => (name, attributes, inner),
+        Node::Let { bindings, inner } => {
+            return write_let(out, bindings, inner, depth);
+        }
+        node => return write_flat(out, node),
+    };
+
+    let mut flat = String::new();
+    write_flat(&mut flat, node);
+    if INDENT.len() * depth + flat.chars().count() <= MAX_WIDTH {
+        out.push_str(&flat);
+        return;
+    }
+
+    let mut start = String::new();
+    write_start(&mut start, name, attributes, !inner.is_empty());
+    let wrap_attributes =
+        attributes.len() > 1 && INDENT.len() * depth + start.chars().count() > MAX_WIDTH;
+    if wrap_attributes {
+        out.push('(');
+        out.push_str(name);
+        for (i, attr) in attributes.iter().enumerate() {
+            new_line(out, depth + 1);
+            write_attr(out, attr, i + 1 == attributes.len() && !inner.is_empty());
+        }
+    } else {
+        out.push_str(&start);
+    }
+
+    for node in inner {
+        new_line(out, depth + 1);
+        write_form(out, node, depth + 1);
+    }
+    out.push(')');
+}
+
+fn write_flat(out: &mut String, node: &Node) {
+    match node {
+        Node::Text(s) => write_string(out, s),
+        Node::Raw(s) => {
+            out.push_str("(!raw ");
+            write_string(out, s);
+            out.push(')');
+        }
+        Node::Comment(s) => {
+            out.push_str("(!-- ");
+            write_string(out, s);
+            out.push(')');
+        }
+        Node::Doctype(s) => {
+            out.push_str("(!doctype ");
+            if s.starts_with(|c: char| c.is_ascii_alphanumeric()) && s.chars().all(is_tag_name_char)
+            {
+                out.push_str(s);
+            } else {
+                write_string(out, s);
+            }
+            out.push(')');
+        }
+        Node::ProcessingInstruction { target, attributes } => {
+            out.push_str("(?");
+            out.push_str(target);
+            write_attrs(out, attributes, false);
+            out.push(')');
+        }
+        Node::Tag {
+            name,
+            attributes,
+            inner,
+        } => {
+            write_start(out, name, attributes, !inner.is_empty());
+            for node in inner {
+                out.push(' ');
+                write_flat(out, node);
+            }
+            out.push(')');
+        }
+        Node::Variable { name, .. } => out.push_str(name),
+        Node::Def { name, value, .. } => {
+            out.push_str("(def ");
+            out.push_str(name);
+            out.push(' ');
+            write_value(out, value);
+            out.push(')');
+        }
+        Node::Let { bindings, inner } => {
+            write_let_start(out, bindings);
+            for node in inner {
+                out.push(' ');
+                write_flat(out, node);
+            }
+            out.push(')');
+        }
+    }
+}
+
+/// Like an element, `let` goes on one line if it fits and otherwise has its bindings on the first
+/// line and the body indented under them
+fn write_let(out: &mut String, bindings: &[(String, Value)], inner: &[Node], depth: usize) {
+    let mut flat = String::new();
+    write_let_start(&mut flat, bindings);
+    let start = flat.len();
+    for node in inner {
+        flat.push(' ');
+        write_flat(&mut flat, node);
+    }
+    flat.push(')');
+    if INDENT.len() * depth + flat.chars().count() <= MAX_WIDTH {
+        out.push_str(&flat);
+        return;
+    }
+
+    out.push_str(&flat[..start]);
+    for node in inner {
+        new_line(out, depth + 1);
+        write_form(out, node, depth + 1);
+    }
+    out.push(')');
+}
+
+/// `(let ((name value) ...)`
+fn write_let_start(out: &mut String, bindings: &[(String, Value)]) {
+    out.push_str("(let (");
+    for (i, (name, value)) in bindings.iter().enumerate() {
+        if i > 0 {
+            out.push(' ');
+        }
+        out.push('(');
+        out.push_str(name);
+        out.push(' ');
+        write_value(out, value);
+        out.push(')');
+    }
+    out.push(')');
+}
+
+/// `(name :attr "value"`, everything but the children and closing paren
+fn write_start(out: &mut String, name: &str, attributes: &[(String, Value)], children: bool) {
+    out.push('(');
+    out.push_str(name);
+    write_attrs(out, attributes, children);
+}
+
+/// `children` being whether anything follows the attributes
+fn write_attrs(out: &mut String, attributes: &[(String, Value)], children: bool) {
+    for (i, attr) in attributes.iter().enumerate() {
+        out.push(' ');
+        write_attr(out, attr, children && i + 1 == attributes.len());
+    }
+}
+
+/// `:name value`, or just `:name` for `true` unless `before_child`, since a child straight after
+/// a bare `:name` would be read as its value
+fn write_attr(out: &mut String, (attr, value): &(String, Value), before_child: bool) {
+    out.push(':');
+    out.push_str(attr);
+    if *value != Value::Bool(true) || before_child {
+        out.push(' ');
+        write_value(out, value);
+    }
+}
+
+fn write_value(out: &mut String, value: &Value) {
+    match value {
+        Value::String(s) => write_string(out, s),
+        Value::Number(n) => out.push_str(n),
+        Value::Symbol(s) => out.push_str(s),
+        Value::Variable { name, .. } => {
+            out.push('$');
+            out.push_str(name);
+        }
+        Value::Bool(b) => out.push_str(&b.to_string()),
+        Value::List(list) => {
+            out.push('(');
+            for (i, value) in list.iter().enumerate() {
+                match value {
+                    // Only a string or variable at the start keeps the list from being read back
+                    // as an element, and either way the word comes out the same
+                    Value::Symbol(s) | Value::Number(s) if i == 0 => write_string(out, s),
+                    _ if i == 0 => write_value(out, value),
+                    _ => {
+                        out.push(' ');
+                        write_value(out, value);
+                    }
+                }
+            }
+            out.push(')');
+        }
+        Value::Map(map) => {
+            out.push('(');
+            for (i, (key, value)) in map.iter().enumerate() {
+                if i > 0 {
+                    out.push(' ');
+                }
+                out.push(':');
+                out.push_str(key);
+                out.push(' ');
+                write_value(out, value);
+            }
+            out.push(')');
+        }
+    }
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn new_line(out: &mut String, depth: usize) {
+    out.push('\n');
+    for _ in 0..depth {
+        out.push_str(INDENT);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::{Document, Node, Value};
+    use crate::Parser;
+
+    /// Convert to HTMLisp, checking it parses back to the same HTML
+    fn round_trip(input: &str) -> String {
+        let document = Parser::new(input).parse_document().unwrap();
+        let lisp = document.to_htmlisp();
+        let reparsed = Parser::new(&lisp).parse_document().unwrap();
+        assert_eq!(reparsed.to_string(), document.to_string());
+        lisp
+    }
+
+    #[test]
+    fn short_forms_stay_on_one_line() {
+        let test = r#"(ul :class "list" (li "one") (li (b "two")))"#;
+        assert_eq!(round_trip(test), format!("{}\n", test));
+    }
+
+    #[test]
+    fn typed_values() {
+        let test = r#"(input :type text :size 10 :step 0.50 :value 007 :max 1e30 :required :hidden false)"#;
+        assert_eq!(round_trip(test), format!("{}\n", test));
+
+        let test = r#"(p :hidden true "text")"#;
+        assert_eq!(round_trip(test), format!("{}\n", test));
+        let long = "x".repeat(90);
+        let test = format!(r#"(p :title "{}" :hidden true "text")"#, long);
+        assert_eq!(
+            round_trip(&test),
+            format!(
+                "(p\n    :title \"{}\"\n    :hidden true\n    \"text\")\n",
+                long
+            )
+        );
+    }
+
+    #[test]
+    fn list_and_map_values() {
+        let test = r#"(p :class ("a" b 1) :style (:color "red" :margin 0) :data-x () "text")"#;
+        assert_eq!(round_trip(test), format!("{}\n", test));
+
+        // Once evaluated, a list can start with a bare word
+        let document = Document {
+            nodes: vec![Node::Tag {
+                name: "p".to_string(),
+                attributes: vec![(
+                    "class".to_string(),
+                    Value::List(vec![
+                        Value::Symbol("a".to_string()),
+                        Value::Number("1".to_string()),
+                    ]),
+                )],
+                inner: vec![],
+            }],
+        };
+        assert_eq!(document.to_htmlisp(), "(p :class (\"a\" 1))\n");
+    }
+
+    #[test]
+    fn def_and_let() {
+        let long = "x".repeat(80);
+        let test = format!(
+            r#"(def title "Home") (let ((a 1) (b (:color red))) (p :style $b $a) (p "{}")) (p title)"#,
+            long
+        );
+        let document = Parser::new(&test).parse_document().unwrap();
+        let lisp = document.to_htmlisp();
+        assert_eq!(
+            lisp,
+            format!(
+                r#"(def title "Home")
+(let ((a 1) (b (:color red)))
+    (p :style $b a)
+    (p "{}"))
+(p title)
+"#,
+                long
+            )
+        );
+        assert_eq!(
+            crate::compile(&lisp, Default::default()),
+            crate::compile(&test, Default::default())
+        );
+    }
+
+    #[test]
+    fn long_forms_are_indented() {
+        let test = r#"(html (head (meta :charset "UTF-8") (title "A page with a title long enough to need wrapping")) (body (p "text")))"#;
+        assert_eq!(
+            round_trip(test),
+            r#"(html
+    (head (meta :charset "UTF-8") (title "A page with a title long enough to need wrapping"))
+    (body (p "text")))
+"#
+        );
+    }
+
+    #[test]
+    fn long_attributes_are_wrapped() {
+        let long = "x".repeat(60);
+        let test = format!(r#"(a :href "{}" :title "{}" "link")"#, long, long);
+        assert_eq!(
+            round_trip(&test),
+            format!(
+                "(a\n    :href \"{}\"\n    :title \"{}\"\n    \"link\")\n",
+                long, long
+            )
+        );
+    }
+
+    #[test]
+    fn special_forms_and_escapes() {
+        let test = r#"(!doctype html) (?xml :version "1.0") (!-- "note") (pre "a\n\t\"b\" \\") (!raw "<br>")"#;
+        assert_eq!(
+            round_trip(test),
+            r#"(!doctype html)
+(?xml :version "1.0")
+(!-- "note")
+(pre "a\n\t\"b\" \\")
+(!raw "<br>")
+"#
+        );
+    }
+}
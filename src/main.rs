@@ -1,13 +1,12 @@
 mod config;
-mod parser;
 
 use config::*;
+use htmlisp::{Error, Options};
 use notify::{watcher, DebouncedEvent, RecursiveMode, Watcher};
-use parser::*;
 use std::{
     env, fmt,
     fs::{self, File},
-    io::{self, Write},
+    io::{self, BufWriter, Read, Write},
     path::Path,
     path::PathBuf,
     process,
@@ -16,33 +15,34 @@ use std::{
 };
 
 fn main() {
-    let mut args = env::args();
-    match Config::new(&mut args).map(run) {
-        Ok(res) => match res {
-            Ok((input_file, output_file)) => {
-                println!(
-                    "\x1b[32;1mSuccess:\x1b[0m {} -> {}",
-                    input_file, output_file
-                );
-            }
-            Err(err) => {
-                eprintln!("\x1b[31;1mError:\x1b[0m {}", err);
-                process::exit(1);
-            }
-        },
+    match Config::new(env::args().skip(1)).map(run) {
+        Ok(Ok(())) => {}
+        Ok(Err(err)) => {
+            eprintln!("\x1b[31;1mError:\x1b[0m {}", err);
+            process::exit(1);
+        }
         Err(err) => {
             eprintln!("\x1b[31;1mError:\x1b[0m {}", err);
+            eprintln!("\x1b[94;1mInfo:\x1b[0m Run `htmlisp --help` for usage");
             process::exit(1);
         }
     };
 }
 
+#[derive(Debug)]
 enum ProgramError {
     ReadInput(io::Error),
-    ParseInput,
+    ParseInput(Error, String),
     CreateOutputFile(io::Error),
     WriteOutput(io::Error),
     WatchDirIncorrect(String),
+    Watch(notify::Error),
+    OutputPath(PathBuf),
+    OutputDirMissing(String),
+    RemoveOutput(io::Error),
+    MoveOutput(io::Error),
+    Unformatted(Vec<String>),
+    FilesFailed(usize),
 }
 
 impl fmt::Display for ProgramError {
@@ -51,117 +51,286 @@ impl fmt::Display for ProgramError {
             f,
             "{}",
             match self {
-                ProgramError::ReadInput(e) =>
-                    format!("Failed to read input file\n({})", e.to_string()),
-                ProgramError::ParseInput => "Failed to parse input file".to_string(),
+                ProgramError::ReadInput(e) => format!("Failed to read input file\n({})", e),
+                ProgramError::ParseInput(e, line) => format!(
+                    "Failed to {} input file\n({})\n{}",
+                    match e {
+                        Error::Parse(_) => "parse",
+                        Error::Eval(_) => "evaluate",
+                    },
+                    e,
+                    fmt_source_line(e, line)
+                ),
                 ProgramError::CreateOutputFile(e) =>
-                    format!("Failed to create output file\n({})", e.to_string()),
-                ProgramError::WriteOutput(e) =>
-                    format!("Failed to write to output file:\n({})", e.to_string()),
+                    format!("Failed to create output file\n({})", e),
+                ProgramError::WriteOutput(e) => format!("Failed to write to output file:\n({})", e),
                 ProgramError::WatchDirIncorrect(p) => format!("'{}' is not a directory", p),
+                ProgramError::Watch(e) => format!("Failed to watch directory\n({})", e),
+                ProgramError::OutputPath(p) => format!(
+                    "Couldn't determine output path for '{}'",
+                    p.to_string_lossy()
+                ),
+                ProgramError::OutputDirMissing(p) => format!(
+                    "'{}' is a directory, so the output must be a directory too",
+                    p
+                ),
+                ProgramError::RemoveOutput(e) => format!("Failed to remove output file\n({})", e),
+                ProgramError::MoveOutput(e) => format!("Failed to move output directory\n({})", e),
+                ProgramError::Unformatted(files) =>
+                    format!("Files aren't formatted:\n{}", files.join("\n")),
+                ProgramError::FilesFailed(count) => format!("{} file(s) had errors", count),
             }
         )
     }
 }
 
-fn run(config: Config) -> Result<(String, String), ProgramError> {
-    if config.help {
-        help();
-        process::exit(0);
-    } else if !config.watch.is_empty() {
-        watch(&config)?
-    } else {
-        read_write(&config)?
+/// Show the offending line with a caret under the error column, e.g.
+/// ```text
+///  3 | (p "hi" x)
+///    |         ^
+/// ```
+fn fmt_source_line(err: &Error, line: &str) -> String {
+    let (line_number, column) = error_location(err);
+    let gutter = line_number.to_string();
+    // Reuse the line's own tabs so the caret lines up however wide they render
+    let padding: String = line
+        .chars()
+        .take(column - 1)
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    format!(
+        "{} | {}\n{} | {}^",
+        gutter,
+        line,
+        " ".repeat(gutter.len()),
+        padding
+    )
+}
+
+fn run(config: Config) -> Result<(), ProgramError> {
+    let subcommand = match config.command {
+        Command::Help(subcommand) => {
+            help(subcommand);
+            return Ok(());
+        }
+        Command::Version => {
+            println!("htmlisp {}", env!("CARGO_PKG_VERSION"));
+            return Ok(());
+        }
+        Command::Run(subcommand) => subcommand,
+    };
+
+    match subcommand {
+        Subcommand::Build if Path::new(&config.input_file).is_dir() => return build_tree(&config),
+        Subcommand::Build => read_write(&config)?,
+        Subcommand::Convert => html_to_htmlisp(&config)?,
+        Subcommand::Watch => return watch(&config),
+        Subcommand::Fmt => return format_files(&config),
+        Subcommand::Check => return check_files(&config),
     }
 
-    Ok((config.input_file, config.output_file))
+    // Stay quiet when writing to stdout, as editor filters often capture stderr too
+    if config.output_file != "-" {
+        println!(
+            "\x1b[32;1mSuccess:\x1b[0m {} -> {}",
+            display_name(&config.input_file),
+            config.output_file
+        );
+    }
+    Ok(())
 }
 
 fn read_write(config: &Config) -> Result<(), ProgramError> {
-    let input = fs::read_to_string(&config.input_file).map_err(ProgramError::ReadInput)?;
-    let html = Parser::new(&input)
-        .parse()
-        .ok_or(ProgramError::ParseInput)?;
+    let input = read_input(&config.input_file)?;
+    let options = Options {
+        prettify: config.prettify,
+        format: config.format.clone(),
+        doctype: config.doctype,
+        xhtml: config.xhtml,
+    };
+    let html = htmlisp::parse(&input, &options).map_err(|e| parse_error(e, &input))?;
 
-    // Create missing directories in the output path
-    let mut output_dir = PathBuf::from(&config.output_file);
-    output_dir.pop(); // Remove the filename and extension from the path
-    fs::create_dir_all(output_dir).map_err(ProgramError::CreateOutputFile)?;
+    write_output(&config.output_file, |output| {
+        htmlisp::render(&html, &options, output)
+    })
+}
 
-    let mut output = File::create(&config.output_file).map_err(ProgramError::CreateOutputFile)?;
+/// Convert an HTML input file into HTMLisp
+fn html_to_htmlisp(config: &Config) -> Result<(), ProgramError> {
+    let input = read_input(&config.input_file)?;
+    let lisp = htmlisp::html_to_htmlisp(&input).map_err(|e| parse_error(e, &input))?;
+
+    write_output(&config.output_file, |output| {
+        output.write_all(lisp.as_bytes())
+    })
+}
 
-    if config.prettify {
-        write!(&mut output, "{}", html.pretty_print(0)).map_err(ProgramError::WriteOutput)?;
+/// Read a whole file, or stdin if `path` is `-`
+fn read_input(path: &str) -> Result<String, ProgramError> {
+    if path == "-" {
+        let mut input = String::new();
+        io::stdin()
+            .read_to_string(&mut input)
+            .map_err(ProgramError::ReadInput)?;
+        Ok(input)
     } else {
-        write!(&mut output, "{}", html).map_err(ProgramError::WriteOutput)?;
+        fs::read_to_string(path).map_err(ProgramError::ReadInput)
+    }
+}
+
+/// The name to show for an input file in messages
+fn display_name(path: &str) -> &str {
+    if path == "-" {
+        "<stdin>"
+    } else {
+        path
+    }
+}
+
+/// Write to a file, creating any missing directories, or to stdout if `path` is `-`
+fn write_output(
+    path: &str,
+    write: impl FnOnce(&mut dyn Write) -> io::Result<()>,
+) -> Result<(), ProgramError> {
+    let mut output: BufWriter<Box<dyn Write>> = if path == "-" {
+        BufWriter::new(Box::new(io::stdout().lock()))
+    } else {
+        create_output_dir(path)?;
+        BufWriter::new(Box::new(
+            File::create(path).map_err(ProgramError::CreateOutputFile)?,
+        ))
+    };
+    write(&mut output).map_err(ProgramError::WriteOutput)?;
+    output.flush().map_err(ProgramError::WriteOutput)
+}
+
+/// Reformat each file in place, or with `--check` list the ones that would change. Errors are
+/// reported as they happen so that one bad file doesn't stop the rest being formatted
+fn format_files(config: &Config) -> Result<(), ProgramError> {
+    let mut unformatted = vec![];
+    let mut failed = 0;
+    for file in &config.files {
+        match format_file(file, config.check) {
+            Ok(true) if config.check => unformatted.push(display_name(file).to_string()),
+            Ok(true) if file != "-" => println!("\x1b[32;1mSuccess:\x1b[0m Formatted {}", file),
+            Ok(_) => {}
+            Err(err) => {
+                eprintln!("\x1b[31;1mError:\x1b[0m {}: {}", display_name(file), err);
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        Err(ProgramError::FilesFailed(failed))
+    } else if !unformatted.is_empty() {
+        Err(ProgramError::Unformatted(unformatted))
+    } else {
+        Ok(())
+    }
+}
+
+/// Format a file, returning whether it changed. With `check` the file is left as it is. Stdin
+/// is formatted to stdout, changed or not, so `fmt -` works as an editor filter
+fn format_file(file: &str, check: bool) -> Result<bool, ProgramError> {
+    let input = read_input(file)?;
+    let formatted = htmlisp::format(&input).map_err(|e| parse_error(e, &input))?;
+    let changed = formatted != input;
+    if check {
+        return Ok(changed);
+    }
+    if file == "-" {
+        write_output(file, |output| output.write_all(formatted.as_bytes()))?;
+    } else if changed {
+        fs::write(file, formatted).map_err(ProgramError::WriteOutput)?;
     }
+    Ok(changed)
+}
+
+/// Parse each file, reporting every error found without writing anything
+fn check_files(config: &Config) -> Result<(), ProgramError> {
+    let mut failed = 0;
+    for file in &config.files {
+        let result = read_input(file).and_then(|input| {
+            htmlisp::parse(&input, &Options::default()).map_err(|e| parse_error(e, &input))
+        });
+        if let Err(err) = result {
+            eprintln!("\x1b[31;1mError:\x1b[0m {}: {}", display_name(file), err);
+            failed += 1;
+        }
+    }
+
+    if failed > 0 {
+        return Err(ProgramError::FilesFailed(failed));
+    }
+    println!(
+        "\x1b[32;1mSuccess:\x1b[0m Checked {} file(s)",
+        config.files.len()
+    );
     Ok(())
 }
 
+/// Attach the line an error happened on so it can be shown with the error
+fn parse_error(err: Error, input: &str) -> ProgramError {
+    let line = input
+        .lines()
+        .nth(error_location(&err).0 - 1)
+        .unwrap_or_default()
+        .to_string();
+    ProgramError::ParseInput(err, line)
+}
+
+/// The 1-based line and column an error happened at
+fn error_location(err: &Error) -> (usize, usize) {
+    match err {
+        Error::Parse(e) => (e.line, e.column),
+        Error::Eval(e) => (e.line, e.column),
+    }
+}
+
+/// Create missing directories in the output path
+fn create_output_dir(output_file: &str) -> Result<(), ProgramError> {
+    let mut output_dir = PathBuf::from(output_file);
+    output_dir.pop(); // Remove the filename and extension from the path
+    fs::create_dir_all(output_dir).map_err(ProgramError::CreateOutputFile)
+}
+
 fn watch(config: &Config) -> Result<(), ProgramError> {
     if !Path::new(&config.watch).is_dir() {
         return Err(ProgramError::WatchDirIncorrect(config.watch.clone()));
     }
 
     let (transmit, receive) = channel();
-    let mut watcher = watcher(transmit, Duration::from_millis(250)).unwrap();
+    let mut watcher = watcher(transmit, Duration::from_millis(250)).map_err(ProgramError::Watch)?;
 
     watcher
         .watch(&config.watch, RecursiveMode::Recursive)
-        .unwrap();
+        .map_err(ProgramError::Watch)?;
     println!(
-        "\x1b[94;1mInfo:\x1b[0m Watching for write events in {}...",
-        &config.watch
+        "\x1b[94;1mInfo:\x1b[0m Watching for changes in {}, writing to {}...",
+        &config.watch, &config.output_file
     );
     loop {
         match receive.recv() {
-            Ok(DebouncedEvent::Write(written_file_path)) => {
-                if written_file_path.extension() != Some("htmlisp".as_ref()) {
-                    continue;
+            // Handle errors in here instead of propagating them so that the loop keeps running
+            Ok(DebouncedEvent::Create(path)) => compile_changed_file(config, &path, "create"),
+            Ok(DebouncedEvent::Write(path)) => compile_changed_file(config, &path, "write"),
+            Ok(DebouncedEvent::Remove(path)) => {
+                if let Err(err) = remove_output(config, &path) {
+                    eprintln!(
+                        "\x1b[31;1mError:\x1b[0m {}: {}",
+                        path.to_string_lossy(),
+                        err
+                    );
                 }
-                // Construct output path
-                let watch_directory = PathBuf::from(&config.watch);
-                let mut output_path = PathBuf::from("output/");
-                let watch_directory_absolute =
-                    watch_directory.canonicalize().map_err(ProgramError::ReadInput)?;
-                let written_file_path_absolute = written_file_path
-                    .canonicalize()
-                    .map_err(ProgramError::ReadInput)?;
-                let written_file_path_relative = written_file_path_absolute
-                    .strip_prefix(watch_directory_absolute)
-                    .expect("Couldn't determine output path");
-                output_path.push(written_file_path_relative);
-                output_path.set_extension("html");
-
-                // Create new config
-                match Config::new(&mut env::args()) {
-                    Ok(mut config) => {
-                        config.input_file =
-                            written_file_path.to_str().unwrap().to_string();
-                        config.output_file = output_path.to_str().unwrap().to_string();
-                        println!(
-                            "\x1b[94;1mInfo:\x1b[0m Compiling due to write event..."
-                        );
-
-                        // Parse changed file with new config
-                        match read_write(&config) {
-                            Ok(()) => println!(
-                                "\x1b[32;1mSuccess:\x1b[0m {} -> {}",
-                                written_file_path_relative.to_string_lossy(),
-                                &config.output_file
-                            ),
-                            // Handle error here instead of propagating it so that the loop keeps running
-                            Err(err) => eprintln!(
-                                "\x1b[31;1mError:\x1b[0m {}: {}",
-                                err,
-                                written_file_path_relative.to_string_lossy()
-                            ),
-                        }
-                    }
-                    Err(err) => {
-                        eprintln!("\x1b[31;1mError:\x1b[0m {}", err);
-                        process::exit(1);
-                    }
+            }
+            Ok(DebouncedEvent::Rename(from, to)) => {
+                if let Err(err) = rename_output(config, &from, &to) {
+                    eprintln!(
+                        "\x1b[31;1mError:\x1b[0m {}: {}",
+                        from.to_string_lossy(),
+                        err
+                    );
                 }
             }
             Ok(_) => {}
@@ -173,26 +342,514 @@ fn watch(config: &Config) -> Result<(), ProgramError> {
     }
 }
 
-fn help() {
-    println!(
-        r#"HTMLisp
+/// Compile a file in the watched directory that was created, written or renamed
+fn compile_changed_file(config: &Config, path: &Path, event: &str) {
+    if path.extension() != Some("htmlisp".as_ref()) || is_in_output_dir(config, path) {
+        return;
+    }
+    println!("\x1b[94;1mInfo:\x1b[0m Compiling due to {} event...", event);
+
+    match compile_tree_file(
+        config,
+        Path::new(&config.watch),
+        Path::new(&config.output_file),
+        path,
+    ) {
+        Ok((input_file, output_file)) => println!(
+            "\x1b[32;1mSuccess:\x1b[0m {} -> {}",
+            input_file, output_file
+        ),
+        Err(err) => eprintln!(
+            "\x1b[31;1mError:\x1b[0m {}: {}",
+            path.to_string_lossy(),
+            err
+        ),
+    }
+}
+
+/// Delete what was compiled from a file removed from the watched directory, so it doesn't go
+/// stale. Removed directories only have their outputs removed if they're empty by then
+fn remove_output(config: &Config, path: &Path) -> Result<(), ProgramError> {
+    if is_in_output_dir(config, path) {
+        return Ok(());
+    }
+    let (_, mut output_path) = tree_output_path(
+        Path::new(&config.watch),
+        Path::new(&config.output_file),
+        path,
+    )?;
+    if output_path.is_dir() {
+        let _ = fs::remove_dir(&output_path);
+        return Ok(());
+    } else if path.extension() != Some("htmlisp".as_ref()) {
+        return Ok(());
+    }
+
+    output_path.set_extension(&config.output_extension);
+    match fs::remove_file(&output_path) {
+        Ok(()) => {
+            println!(
+                "\x1b[32;1mSuccess:\x1b[0m Removed {}",
+                output_path.to_string_lossy()
+            );
+            Ok(())
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(ProgramError::RemoveOutput(e)),
+    }
+}
+
+/// Follow a rename in the watched directory. Renamed directories have their outputs moved, and
+/// files have their old output removed and are compiled under the new name. Editors that save by
+/// renaming a temporary file over the original end up here too
+fn rename_output(config: &Config, from: &Path, to: &Path) -> Result<(), ProgramError> {
+    if to.is_dir() {
+        let source_dir = Path::new(&config.watch);
+        let output_dir = Path::new(&config.output_file);
+        let (_, from_output) = tree_output_path(source_dir, output_dir, from)?;
+        let (_, to_output) = tree_output_path(source_dir, output_dir, to)?;
+        if from_output.is_dir() && !is_in_output_dir(config, from) {
+            create_output_dir(&path_to_string(&to_output)?)?;
+            fs::rename(&from_output, &to_output).map_err(ProgramError::MoveOutput)?;
+            println!(
+                "\x1b[32;1mSuccess:\x1b[0m Moved {} -> {}",
+                from_output.to_string_lossy(),
+                to_output.to_string_lossy()
+            );
+        }
+        return Ok(());
+    }
+
+    remove_output(config, from)?;
+    compile_changed_file(config, to, "rename");
+    Ok(())
+}
+
+/// Whether a path is in the output directory, which may well be inside the watched one
+fn is_in_output_dir(config: &Config, path: &Path) -> bool {
+    match (
+        canonicalize_existing(path),
+        canonicalize_existing(Path::new(&config.output_file)),
+    ) {
+        (Ok(path), Ok(output_dir)) => path.starts_with(output_dir),
+        _ => false,
+    }
+}
+
+/// Compile every `.htmlisp` file under the input directory to the same place in the output
+/// directory, carrying on past errors and then summarising them
+fn build_tree(config: &Config) -> Result<(), ProgramError> {
+    if config.output_file == "-" {
+        return Err(ProgramError::OutputDirMissing(config.input_file.clone()));
+    }
+    let source_dir = Path::new(&config.input_file);
+    let output_dir = Path::new(&config.output_file);
+
+    let mut files = vec![];
+    htmlisp_files(source_dir, &mut files).map_err(ProgramError::ReadInput)?;
+    let mut failed = 0;
+    for file in &files {
+        match compile_tree_file(config, source_dir, output_dir, file) {
+            Ok((input_file, output_file)) => println!(
+                "\x1b[32;1mSuccess:\x1b[0m {} -> {}",
+                input_file, output_file
+            ),
+            Err(err) => {
+                eprintln!(
+                    "\x1b[31;1mError:\x1b[0m {}: {}",
+                    file.to_string_lossy(),
+                    err
+                );
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        eprintln!(
+            "\x1b[94;1mInfo:\x1b[0m Compiled {} of {} file(s)",
+            files.len() - failed,
+            files.len()
+        );
+        return Err(ProgramError::FilesFailed(failed));
+    }
+    println!("\x1b[32;1mSuccess:\x1b[0m Compiled {} file(s)", files.len());
+    Ok(())
+}
+
+/// Collect every `.htmlisp` file under `dir`, sorted so builds always run in the same order
+fn htmlisp_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            htmlisp_files(&path, files)?;
+        } else if path.extension() == Some("htmlisp".as_ref()) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Compile a file in `source_dir` to the same relative path in `output_dir`, with the output
+/// extension, returning its path relative to `source_dir` and the path of the output file
+fn compile_tree_file(
+    config: &Config,
+    source_dir: &Path,
+    output_dir: &Path,
+    file: &Path,
+) -> Result<(String, String), ProgramError> {
+    let (file_relative, mut output_path) = tree_output_path(source_dir, output_dir, file)?;
+    output_path.set_extension(&config.output_extension);
+
+    let config = Config {
+        input_file: path_to_string(file)?,
+        output_file: path_to_string(&output_path)?,
+        ..config.clone()
+    };
+    read_write(&config)?;
+
+    Ok((
+        file_relative.to_string_lossy().into_owned(),
+        config.output_file,
+    ))
+}
+
+/// Where a path in `source_dir` goes in `output_dir`, keeping its extension, along with its path
+/// relative to `source_dir`. The path doesn't have to exist any more, so that the outputs of
+/// removed files can be found
+fn tree_output_path(
+    source_dir: &Path,
+    output_dir: &Path,
+    path: &Path,
+) -> Result<(PathBuf, PathBuf), ProgramError> {
+    let source_dir_absolute = source_dir.canonicalize().map_err(ProgramError::ReadInput)?;
+    let path_absolute = canonicalize_existing(path).map_err(ProgramError::ReadInput)?;
+    let path_relative = path_absolute
+        .strip_prefix(source_dir_absolute)
+        .map_err(|_| ProgramError::OutputPath(path.to_path_buf()))?;
+    Ok((path_relative.to_path_buf(), output_dir.join(path_relative)))
+}
+
+/// Canonicalize as much of a path as exists, then add the rest back on
+fn canonicalize_existing(path: &Path) -> io::Result<PathBuf> {
+    match path.canonicalize() {
+        Ok(path) => Ok(path),
+        Err(e) => match (path.parent(), path.file_name()) {
+            (Some(parent), Some(name)) if parent.as_os_str().is_empty() => {
+                Ok(Path::new(".").canonicalize()?.join(name))
+            }
+            (Some(parent), Some(name)) => Ok(canonicalize_existing(parent)?.join(name)),
+            _ => Err(e),
+        },
+    }
+}
+
+fn path_to_string(path: &Path) -> Result<String, ProgramError> {
+    path.to_str()
+        .map(str::to_string)
+        .ok_or_else(|| ProgramError::OutputPath(path.to_path_buf()))
+}
+
+/// Flags shared by the subcommands that compile HTML
+const COMPILE_FLAGS: &str = r#"Compile Flags:
+    -p/--prettify Output prettified HTML
+    --indent <spaces|tab> Indent prettified HTML with this many spaces, or tabs (the default)
+    --max-width <columns> Keep prettified lines within this width where possible
+    --wrap-attributes <never|auto|always> When to put attributes on their own lines,
+        auto meaning when they don't fit in --max-width
+    --trailing-newline End the output with a newline
+    -d/--doctype Add <!DOCTYPE html> before a root html element if there isn't one
+    --xhtml Write valueless attributes as name="" rather than just name"#;
+
+fn help(subcommand: Option<Subcommand>) {
+    let help = match subcommand {
+        None => r#"HTMLisp
 
 Description:
     This program takes in a file of HTMLisp,
     parses it and outputs normal HTML
 
 Usage:
-    htmlisp -i/--input <input file> -o/--output <output file>
-    
-Optional Flags:
-    -p/--prettify Output prettified HTML
-    -w/--watch <directory> Watch a directory for changes and re-compile:
-        outputs to <working directory>/output/,
-        preserves input directory structure,
-        and makes the -i/--input and -o/--output flags optional
+    htmlisp <command> [flags] [--] [arguments]
+
+Commands:
+    build    Compile an HTMLisp file to HTML
+    watch    Watch a directory for changes and re-compile
+    fmt      Rewrite HTMLisp files in the canonical style
+    check    Report errors in HTMLisp files without writing anything
+    convert  Convert an HTML file to HTMLisp
+
+    Run `htmlisp <command> --help` for a command's flags.
+    Without a command, `-i`/`-o` build, `-w` watches and `--html2lisp` converts.
+
+Flags:
+    -h/--help Print this help
+    -V/--version Print the version
+
+Note:
+    `-` as a file means stdin, or stdout for output files.
+    Arguments after `--` are never read as flags."#
+            .to_string(),
+        Some(Subcommand::Build) => format!(
+            r#"Compile an HTMLisp file to HTML
+
+Usage:
+    htmlisp build [flags] [input file] [output file]
+    htmlisp build [flags] -i/--input <input file> -o/--output <output file>
+    htmlisp build [flags] <input directory> <output directory>
+
+    The input and output default to stdin and stdout.
+    Given a directory, every .htmlisp file in it is compiled to the same path
+    in the output directory, with an .html extension unless --extension says otherwise
+
+{}
 
 Note:
     If the output file already exists, it will be overwritten
-    and if it does not exist, it will be created"#
-    );
+    and if it does not exist, it will be created"#,
+            COMPILE_FLAGS
+        ),
+        Some(Subcommand::Watch) => format!(
+            r#"Watch a directory for changes and re-compile
+
+Usage:
+    htmlisp watch [flags] <directory>
+
+    Preserves the input directory structure in the output directory,
+    compiling new and renamed files and deleting the outputs of removed ones
+
+Flags:
+    -o/--output <directory> Where to write compiled files, <directory>/output by default
+    --extension <extension> The extension compiled files get, html by default
+
+{}"#,
+            COMPILE_FLAGS
+        ),
+        Some(Subcommand::Fmt) => r#"Rewrite HTMLisp files in the canonical style, keeping comments
+
+Usage:
+    htmlisp fmt [--check] <files...>
+
+Flags:
+    --check Don't rewrite anything, but fail if any file isn't formatted
+
+Note:
+    `htmlisp fmt -` formats stdin to stdout"#
+            .to_string(),
+        Some(Subcommand::Check) => r#"Report errors in HTMLisp files without writing anything
+
+Usage:
+    htmlisp check <files...>"#
+            .to_string(),
+        Some(Subcommand::Convert) => r#"Convert an HTML file to HTMLisp
+
+Usage:
+    htmlisp convert [input file] [output file]
+    htmlisp convert -i/--input <input file> -o/--output <output file>
+
+    The input and output default to stdin and stdout"#
+            .to_string(),
+    };
+    // Ignore errors, so that piping help into something like `head` doesn't panic
+    let _ = writeln!(io::stdout(), "{}", help);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty directory for one test to work in
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("htmlisp-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.canonicalize().unwrap()
+    }
+
+    #[test]
+    fn canonicalize_missing_paths() {
+        let dir = temp_dir("canonicalize");
+        fs::create_dir(dir.join("src")).unwrap();
+
+        let path = dir
+            .join("src")
+            .join("..")
+            .join("src")
+            .join("gone")
+            .join("a.htmlisp");
+        assert_eq!(
+            canonicalize_existing(&path).unwrap(),
+            dir.join("src").join("gone").join("a.htmlisp")
+        );
+        assert_eq!(
+            canonicalize_existing(Path::new("missing.htmlisp")).unwrap(),
+            env::current_dir()
+                .unwrap()
+                .canonicalize()
+                .unwrap()
+                .join("missing.htmlisp")
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn tree_output_paths() {
+        let dir = temp_dir("tree-output");
+        let source_dir = dir.join("src");
+        fs::create_dir_all(source_dir.join("blog")).unwrap();
+        fs::write(source_dir.join("blog").join("post.htmlisp"), "").unwrap();
+        let output_dir = Path::new("public");
+
+        let (relative, output) = tree_output_path(
+            &source_dir,
+            output_dir,
+            &source_dir.join("blog").join("post.htmlisp"),
+        )
+        .unwrap();
+        assert_eq!(relative, Path::new("blog").join("post.htmlisp"));
+        assert_eq!(output, output_dir.join("blog").join("post.htmlisp"));
+
+        // Removed files are still placed, so their output can be removed too
+        let (relative, _) =
+            tree_output_path(&source_dir, output_dir, &source_dir.join("old.htmlisp")).unwrap();
+        assert_eq!(relative, Path::new("old.htmlisp"));
+
+        let outside = tree_output_path(&source_dir, output_dir, &dir.join("other.htmlisp"));
+        assert!(matches!(outside, Err(ProgramError::OutputPath(_))));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn build_directory_tree() {
+        let dir = temp_dir("build-tree");
+        let source_dir = dir.join("src");
+        let output_dir = dir.join("public");
+        fs::create_dir_all(source_dir.join("blog")).unwrap();
+        fs::write(source_dir.join("index.htmlisp"), r#"(p "home")"#).unwrap();
+        fs::write(
+            source_dir.join("blog").join("post.htmlisp"),
+            r#"(p "post")"#,
+        )
+        .unwrap();
+        fs::write(source_dir.join("blog").join("broken.htmlisp"), "(p").unwrap();
+        fs::write(source_dir.join("notes.txt"), "").unwrap();
+        let config = Config::new([
+            "build".to_string(),
+            source_dir.to_str().unwrap().to_string(),
+            output_dir.to_str().unwrap().to_string(),
+        ])
+        .unwrap();
+
+        // The broken file doesn't stop the others being compiled
+        assert!(matches!(
+            build_tree(&config),
+            Err(ProgramError::FilesFailed(1))
+        ));
+        let read = |path: PathBuf| fs::read_to_string(path).unwrap();
+        assert_eq!(read(output_dir.join("index.html")), "<p>home</p>");
+        assert_eq!(
+            read(output_dir.join("blog").join("post.html")),
+            "<p>post</p>"
+        );
+        assert!(!output_dir.join("blog").join("broken.html").exists());
+        assert!(!output_dir.join("notes.txt").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// A watched directory with one file in it and another in `blog`, both already compiled
+    fn watched_site(name: &str) -> (PathBuf, Config) {
+        let dir = temp_dir(name);
+        let site = dir.join("site");
+        fs::create_dir_all(site.join("blog")).unwrap();
+        fs::create_dir_all(site.join("output").join("blog")).unwrap();
+        fs::write(site.join("index.htmlisp"), r#"(p "home")"#).unwrap();
+        fs::write(site.join("blog").join("post.htmlisp"), r#"(p "post")"#).unwrap();
+        fs::write(site.join("output").join("index.html"), "<p>home</p>").unwrap();
+        let post = site.join("output").join("blog").join("post.html");
+        fs::write(post, "<p>post</p>").unwrap();
+        let config =
+            Config::new(["watch".to_string(), site.to_str().unwrap().to_string()]).unwrap();
+        (dir, config)
+    }
+
+    #[test]
+    fn remove_outputs() {
+        let (dir, config) = watched_site("remove-output");
+        let site = dir.join("site");
+
+        fs::remove_file(site.join("index.htmlisp")).unwrap();
+        remove_output(&config, &site.join("index.htmlisp")).unwrap();
+        assert!(!site.join("output").join("index.html").exists());
+
+        // Directories' outputs go once they're empty
+        let post = site.join("blog").join("post.htmlisp");
+        fs::remove_dir_all(site.join("blog")).unwrap();
+        remove_output(&config, &post).unwrap();
+        remove_output(&config, &site.join("blog")).unwrap();
+        assert!(!site.join("output").join("blog").exists());
+        assert!(site.join("output").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rename_outputs() {
+        let (dir, config) = watched_site("rename-output");
+        let site = dir.join("site");
+        let output = site.join("output");
+
+        fs::rename(site.join("blog"), site.join("news")).unwrap();
+        rename_output(&config, &site.join("blog"), &site.join("news")).unwrap();
+        assert!(!output.join("blog").exists());
+        assert_eq!(
+            fs::read_to_string(output.join("news").join("post.html")).unwrap(),
+            "<p>post</p>"
+        );
+
+        // Renamed files are compiled under their new name
+        fs::rename(site.join("index.htmlisp"), site.join("home.htmlisp")).unwrap();
+        rename_output(
+            &config,
+            &site.join("index.htmlisp"),
+            &site.join("home.htmlisp"),
+        )
+        .unwrap();
+        assert!(!output.join("index.html").exists());
+        assert_eq!(
+            fs::read_to_string(output.join("home.html")).unwrap(),
+            "<p>home</p>"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn output_dir_paths() {
+        let dir = temp_dir("output-dir");
+        let site = dir.join("site");
+        fs::create_dir_all(site.join("output")).unwrap();
+        fs::create_dir_all(site.join("blog")).unwrap();
+        let config =
+            Config::new(["watch".to_string(), site.to_str().unwrap().to_string()]).unwrap();
+
+        assert!(is_in_output_dir(
+            &config,
+            &site.join("output").join("index.html")
+        ));
+        assert!(is_in_output_dir(
+            &config,
+            &site.join("output").join("new").join("a.html")
+        ));
+        assert!(is_in_output_dir(
+            &config,
+            &site.join("blog").join("..").join("output").join("a.html")
+        ));
+        assert!(!is_in_output_dir(&config, &site.join("index.htmlisp")));
+        assert!(!is_in_output_dir(
+            &config,
+            &site.join("output-old").join("a.html")
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
 }
@@ -1,184 +1,883 @@
+use crate::render::{closes_raw_text, is_void_element};
 use core::fmt;
-use std::{iter::Peekable, str::Chars};
+use std::{iter::Peekable, str::CharIndices};
 
 #[derive(Debug)]
 pub enum Node {
     Text(String),
+    /// Trusted HTML, written out without escaping
+    Raw(String),
+    /// An HTML comment, as opposed to a source comment which is dropped
+    Comment(String),
+    /// `<!DOCTYPE ...>`, holding everything after the word `DOCTYPE`
+    Doctype(String),
+    /// `<?target ...?>`, like `<?xml version="1.0"?>`
+    ProcessingInstruction {
+        target: String,
+        attributes: Vec<(String, Value)>,
+    },
     Tag {
         name: String,
-        attributes: Vec<(String, String)>,
+        attributes: Vec<(String, Value)>,
+        inner: Vec<Node>,
+    },
+    /// A bare word or `$name` where a child would go, standing for a value bound by `def` or
+    /// `let`. `offset` is where it is in the source, to report it if nothing is bound to it. This and the two
+    /// variants below only come from the parser and have to be evaluated away before rendering
+    Variable {
+        name: String,
+        offset: usize,
+    },
+    /// `(def name value)`, binding `name` for the rest of the document
+    Def {
+        name: String,
+        value: Value,
+        offset: usize,
+    },
+    /// `(let ((name value) ...) body...)`, binding names for just the body
+    Let {
+        bindings: Vec<(String, Value)>,
         inner: Vec<Node>,
     },
 }
 
+/// An attribute's value
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    /// `:width 100`, kept as it's written so `1.0` and `007` come out the same
+    Number(String),
+    /// A bare word, like `text` in `:type text`. It's always just the word, even if a variable has
+    /// the same name
+    Symbol(String),
+    /// `$name`, standing for a value bound by `def` or `let`. `offset` is where it is in the source
+    Variable {
+        name: String,
+        offset: usize,
+    },
+    /// `true`, or an attribute with no value. `false` attributes aren't rendered at all
+    Bool(bool),
+    /// `("btn" "btn-primary")`, written out separated by spaces
+    List(Vec<Value>),
+    /// `(:color "red" :margin "0 auto")`, written out as CSS declarations
+    Map(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// The value as it's written in an attribute, or `None` for booleans and for variables, which
+    /// only evaluating gives a value. Booleans in lists and maps are left out, but a variable
+    /// anywhere in one leaves it without text
+    pub fn as_text(&self) -> Option<String> {
+        match self {
+            Value::String(s) | Value::Symbol(s) => Some(s.clone()),
+            Value::Number(n) => Some(n.clone()),
+            Value::Bool(_) | Value::Variable { .. } => None,
+            Value::List(list) => Some(
+                list.iter()
+                    .filter(|value| !matches!(value, Value::Bool(_)))
+                    .map(Value::as_text)
+                    .collect::<Option<Vec<_>>>()?
+                    .into_iter()
+                    .filter(|text| !text.is_empty())
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            ),
+            Value::Map(map) => Some(
+                map.iter()
+                    .filter(|(_, value)| !matches!(value, Value::Bool(_)))
+                    .map(|(key, value)| Some(format!("{}: {}", key, value.as_text()?)))
+                    .collect::<Option<Vec<_>>>()?
+                    .join("; "),
+            ),
+        }
+    }
+}
+
+/// Every top-level form in a file
+#[derive(Debug)]
+pub struct Document {
+    pub nodes: Vec<Node>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Token {
+    OpenParen,
+    CloseParen,
+    Quote,
+    Colon,
+    Ident,
+    Dollar,
+    Number,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::OpenParen => write!(f, "`(`"),
+            Token::CloseParen => write!(f, "`)`"),
+            Token::Quote => write!(f, "`\"`"),
+            Token::Colon => write!(f, "`:`"),
+            Token::Ident => write!(f, "identifier"),
+            Token::Dollar => write!(f, "`$`"),
+            Token::Number => write!(f, "number"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// Byte offset into the input
+    pub offset: usize,
+    /// 1-based line number
+    pub line: usize,
+    /// 1-based column, counted in characters
+    pub column: usize,
+    pub kind: ParseErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseErrorKind {
+    Unexpected {
+        expected: Vec<Token>,
+        /// `None` if the input ended
+        found: Option<char>,
+    },
+    VoidElementChildren(String),
+    UnknownForm(String),
+    UnterminatedString,
+    UnknownEscape(char),
+    InvalidUnicodeEscape,
+    UnterminatedComment,
+    InvalidComment,
+    InvalidDoctype,
+    /// A doctype anywhere but the top level of a document
+    MisplacedDoctype,
+    InvalidProcessingInstruction,
+    /// Text in a `script` or `style` element that would end it early
+    RawTextEnd(String),
+    /// A name in HTML being converted that HTMLisp has no way to write
+    UnsupportedName(String),
+    InvalidNumber(String),
+    DuplicateAttribute(String),
+    /// Forms nested more than `MAX_DEPTH` deep
+    TooDeep,
+}
+
+impl ParseError {
+    pub(crate) fn new(source: &str, offset: usize, kind: ParseErrorKind) -> Self {
+        let (line, column) = location(source, offset);
+        Self {
+            offset,
+            line,
+            column,
+            kind,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.kind
+        )
+    }
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseErrorKind::Unexpected { expected, found } => {
+                write!(f, "expected ")?;
+                for (i, token) in expected.iter().enumerate() {
+                    if i > 0 && i == expected.len() - 1 {
+                        write!(f, " or ")?;
+                    } else if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", token)?;
+                }
+                match found {
+                    Some(c) => write!(f, ", found `{}`", c.escape_debug()),
+                    None => write!(f, ", found end of input"),
+                }
+            }
+            ParseErrorKind::VoidElementChildren(name) => {
+                write!(f, "`{}` is a void element and can't have children", name)
+            }
+            ParseErrorKind::UnknownForm(name) => write!(f, "unknown form `(!{}`", name),
+            ParseErrorKind::UnterminatedString => write!(f, "string is never closed"),
+            ParseErrorKind::UnknownEscape(c) => {
+                write!(f, "unknown escape sequence `\\{}`", c.escape_debug())
+            }
+            ParseErrorKind::UnterminatedComment => write!(f, "block comment is never closed"),
+            ParseErrorKind::InvalidComment => {
+                write!(f, "HTML comments can't contain `-->` or `--!>`")
+            }
+            ParseErrorKind::InvalidDoctype => write!(f, "doctypes can't contain `>`"),
+            ParseErrorKind::MisplacedDoctype => {
+                write!(
+                    f,
+                    "doctypes can only be used at the top level of a document"
+                )
+            }
+            ParseErrorKind::InvalidProcessingInstruction => {
+                write!(f, "processing instructions can't contain `?>`")
+            }
+            ParseErrorKind::RawTextEnd(name) => write!(f, "`{0}` can't contain `</{0}`", name),
+            ParseErrorKind::UnsupportedName(name) => {
+                write!(f, "`{}` can't be written as an HTMLisp name", name)
+            }
+            ParseErrorKind::InvalidNumber(number) => write!(f, "`{}` isn't a valid number", number),
+            ParseErrorKind::DuplicateAttribute(attr) => {
+                write!(f, "`{}` is given more than once", attr)
+            }
+            ParseErrorKind::TooDeep => {
+                write!(f, "forms can't be nested more than {} deep", MAX_DEPTH)
+            }
+            ParseErrorKind::InvalidUnicodeEscape => write!(
+                f,
+                "invalid unicode escape, expected 1 to 6 hex digits in braces like `\\u{{1F600}}`"
+            ),
+        }
+    }
+}
+
+/// The 1-based line and column, counted in characters, of `offset` in `source`
+pub(crate) fn location(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset];
+    (
+        before.matches('\n').count() + 1,
+        before.chars().rev().take_while(|&c| c != '\n').count() + 1,
+    )
+}
+
+/// What a child can start with
+const CHILD_TOKENS: [Token; 4] = [Token::OpenParen, Token::Quote, Token::Ident, Token::Dollar];
+
+/// What a value can start with
+const VALUE_TOKENS: [Token; 5] = [
+    Token::Quote,
+    Token::Number,
+    Token::Ident,
+    Token::Dollar,
+    Token::OpenParen,
+];
+
+/// How deeply forms can nest, which keeps recursing through malformed input from overflowing
+/// the stack
+pub(crate) const MAX_DEPTH: usize = 256;
+
 pub struct Parser<'input> {
-    input: Peekable<Chars<'input>>,
+    source: &'input str,
+    input: Peekable<CharIndices<'input>>,
+    /// How many forms deep the parser is
+    depth: usize,
 }
 
 impl<'input> Parser<'input> {
     pub fn new(input: &'input str) -> Self {
         Self {
-            input: input.chars().peekable(),
+            source: input,
+            input: input.char_indices().peekable(),
+            depth: 0,
+        }
+    }
+
+    /// Parse forms until the end of the input
+    pub fn parse_document(&mut self) -> Result<Document, ParseError> {
+        let mut nodes = vec![];
+        loop {
+            self.skip_whitespace()?;
+            if self.peek().is_none() {
+                break;
+            }
+            nodes.push(self.parse()?);
         }
+
+        Ok(Document { nodes })
     }
 
-    pub fn parse(&mut self) -> Option<Node> {
-        match self.input.peek()? {
-            '(' => self.parse_tag(),
-            '"' => self.parse_string().map(Node::Text),
-            _ => unreachable!(),
+    /// Parse a single form, leaving anything after it unread. Like a document, it has to be
+    /// evaluated, with [`Node::evaluate`], before it can be rendered
+    pub fn parse(&mut self) -> Result<Node, ParseError> {
+        self.nested(|parser| parser.parse_form(None))
+    }
+
+    /// A form inside the element `parent`, if it's inside one
+    fn parse_form(&mut self, parent: Option<&str>) -> Result<Node, ParseError> {
+        self.skip_whitespace()?;
+        match self.peek() {
+            Some('(') => self.parse_tag(parent),
+            Some('"') => self.parse_string().map(Node::Text),
+            Some('$') => {
+                let (name, offset) = self.parse_variable()?;
+                Ok(Node::Variable { name, offset })
+            }
+            // A bare word can't be anything else here, unlike in an attribute
+            Some(c) if starts_symbol(c) => {
+                let offset = self.offset();
+                let name = self.parse_ident(is_symbol_char)?;
+                Ok(Node::Variable { name, offset })
+            }
+            _ => Err(self.error(CHILD_TOKENS.to_vec())),
         }
     }
 
-    fn parse_tag(&mut self) -> Option<Node> {
+    fn parse_tag(&mut self, parent: Option<&str>) -> Result<Node, ParseError> {
+        let offset = self.offset();
         self.input.next();
-        let name = self.parse_ident()?;
+        match self.peek() {
+            Some('!') => return self.parse_special_form(parent),
+            Some('?') => return self.parse_processing_instruction(),
+            _ => {}
+        }
+        let name = self.parse_tag_name()?;
+        // `def` and `let` are taken, but `(def.x)` is still an element
+        if !matches!(self.peek(), Some('.' | '#')) {
+            match name.as_str() {
+                "def" => return self.parse_def(offset),
+                "let" => return self.parse_let(parent),
+                _ => {}
+            }
+        }
+        let mut attributes = self.parse_shorthand()?;
+
+        self.skip_whitespace()?;
+
+        while self.peek() == Some(':') {
+            let offset = self.offset();
+            let attribute = self.parse_attribute()?;
+            self.add_attribute(&mut attributes, attribute, offset)?;
+            self.skip_whitespace()?;
+        }
+
+        if is_void_element(&name) && self.peek().is_some_and(starts_node) {
+            let offset = self.offset();
+            return Err(self.error_at(offset, ParseErrorKind::VoidElementChildren(name)));
+        }
+        let inner = self.parse_children(Some(&name))?;
+
+        if self.peek() != Some(')') {
+            let mut expected = [&CHILD_TOKENS[..], &[Token::CloseParen]].concat();
+            if inner.is_empty() {
+                expected.insert(0, Token::Colon);
+            }
+            return Err(self.error(expected));
+        }
+        self.input.next();
+
+        Ok(Node::Tag {
+            name,
+            attributes,
+            inner,
+        })
+    }
+
+    /// Children of `parent` up to, but not including, whatever ends them
+    fn parse_children(&mut self, parent: Option<&str>) -> Result<Vec<Node>, ParseError> {
+        let mut inner = vec![];
+        // The text since the last child that isn't text, which is written out as one
+        let mut text = String::new();
+        while self.peek().is_some_and(starts_node) {
+            let offset = self.offset();
+            let node = self.nested(|parser| parser.parse_form(parent))?;
+            match &node {
+                Node::Text(s) => text.push_str(s),
+                _ => text.clear(),
+            }
+            // Text in `script` and `style` isn't escaped, so can't be allowed to end them early
+            if let Some(parent) = parent.filter(|parent| closes_raw_text(parent, &text)) {
+                let kind = ParseErrorKind::RawTextEnd(parent.to_string());
+                return Err(self.error_at(offset, kind));
+            }
+            inner.push(node);
+            self.skip_whitespace()?;
+        }
+        Ok(inner)
+    }
+
+    /// `(def name value)`, from just after the `def`
+    fn parse_def(&mut self, offset: usize) -> Result<Node, ParseError> {
+        let (name, value) = self.parse_binding()?;
+        Ok(Node::Def {
+            name,
+            value,
+            offset,
+        })
+    }
 
+    /// `(let ((name value) ...) body...)`, from just after the `let`. The body goes straight into
+    /// `parent`
+    fn parse_let(&mut self, parent: Option<&str>) -> Result<Node, ParseError> {
+        self.skip_whitespace()?;
+        if self.peek() != Some('(') {
+            return Err(self.error(vec![Token::OpenParen]));
+        }
+        self.input.next();
         self.skip_whitespace()?;
 
-        let mut attributes: Vec<(String, String)> = vec![];
-        while self.input.peek()? == &':' {
+        let mut bindings = vec![];
+        while self.peek() == Some('(') {
             self.input.next();
+            bindings.push(self.parse_binding()?);
+            self.skip_whitespace()?;
+        }
+        if self.peek() != Some(')') {
+            return Err(self.error(vec![Token::OpenParen, Token::CloseParen]));
+        }
+        self.input.next();
+        self.skip_whitespace()?;
 
-            let attr = self.parse_ident()?;
+        let inner = self.parse_children(parent)?;
+        if self.peek() != Some(')') {
+            return Err(self.error([&CHILD_TOKENS[..], &[Token::CloseParen]].concat()));
+        }
+        self.input.next();
 
-            self.skip_whitespace();
+        Ok(Node::Let { bindings, inner })
+    }
+
+    /// `name value)`, the end of a `def` or one of a `let`'s bindings
+    fn parse_binding(&mut self) -> Result<(String, Value), ParseError> {
+        self.skip_whitespace()?;
+        if !self.peek().is_some_and(starts_symbol) {
+            return Err(self.error(vec![Token::Ident]));
+        }
+        let name = self.parse_ident(is_symbol_char)?;
+        self.skip_whitespace()?;
+
+        let value = match self.parse_value()? {
+            Some(value) => value,
+            None => return Err(self.error(VALUE_TOKENS.to_vec())),
+        };
+        self.skip_whitespace()?;
+        if self.peek() != Some(')') {
+            return Err(self.error(vec![Token::CloseParen]));
+        }
+        self.input.next();
 
-            let value = if self.input.peek()? == &':' {
-                "".to_string()
-            } else if self.input.peek()? != &'"' {
-                return None;
-            } else {
-                self.parse_string()?
+        Ok((name, value))
+    }
+
+    /// `.class` and `#id` straight after a tag name, as in `(div.card#main ...)`
+    fn parse_shorthand(&mut self) -> Result<Vec<(String, Value)>, ParseError> {
+        let mut attributes = vec![];
+        loop {
+            let offset = self.offset();
+            let attr = match (self.peek(), self.peek_second()) {
+                (Some('.'), _) => "class",
+                // `#|` and `#_` are still comments, even straight after a name
+                (Some('#'), Some(c)) if c != '|' && c != '_' => "id",
+                _ => return Ok(attributes),
             };
-            attributes.push((attr, value));
+            self.input.next();
+            let value = Value::String(self.parse_ident(is_tag_name_char)?);
+            self.add_attribute(&mut attributes, (attr.to_string(), value), offset)?;
+        }
+    }
+
+    /// Add an attribute, rejecting any given twice other than `class` and `style`. Those are
+    /// merged when the document is evaluated, once any symbols in them are resolved. Like in HTML,
+    /// names are compared ignoring case
+    fn add_attribute(
+        &self,
+        attributes: &mut Vec<(String, Value)>,
+        (attr, value): (String, Value),
+        offset: usize,
+    ) -> Result<(), ParseError> {
+        if !attr.eq_ignore_ascii_case("class")
+            && !attr.eq_ignore_ascii_case("style")
+            && attributes
+                .iter()
+                .any(|(a, _)| a.eq_ignore_ascii_case(&attr))
+        {
+            return Err(self.error_at(offset, ParseErrorKind::DuplicateAttribute(attr)));
+        }
+        attributes.push((attr, value));
+        Ok(())
+    }
+
+    /// `:name value`, or just `:name`. Anything after a name that isn't a value is left to the
+    /// caller, so valueless attributes can come last or before children
+    fn parse_attribute(&mut self) -> Result<(String, Value), ParseError> {
+        self.input.next();
+
+        let attr = self.parse_ident(is_attr_name_char)?;
+
+        self.skip_whitespace()?;
+
+        let value = self.parse_value()?.unwrap_or(Value::Bool(true));
+        Ok((attr, value))
+    }
+
+    /// A string, number, symbol, boolean, variable, list or map, or `None` if what's next isn't a
+    /// value
+    fn parse_value(&mut self) -> Result<Option<Value>, ParseError> {
+        let value = match (self.peek(), self.peek_second()) {
+            (Some('"'), _) => Value::String(self.parse_string()?),
+            (Some('$'), _) => {
+                let (name, offset) = self.parse_variable()?;
+                Value::Variable { name, offset }
+            }
+            (Some('('), _) if self.peek_list_value() => self.nested(Self::parse_list_value)?,
+            (Some(c), _) if c.is_ascii_digit() => self.parse_number()?,
+            (Some('-' | '+' | '.'), Some(c)) if c.is_ascii_digit() => self.parse_number()?,
+            (Some(c), _) if starts_symbol(c) => match self.parse_ident(is_symbol_char)?.as_str() {
+                "true" => Value::Bool(true),
+                "false" => Value::Bool(false),
+                symbol => Value::Symbol(symbol.to_string()),
+            },
+            _ => return Ok(None),
+        };
+        Ok(Some(value))
+    }
+
+    /// `$name`, returning the name and where the `$` is
+    fn parse_variable(&mut self) -> Result<(String, usize), ParseError> {
+        let offset = self.offset();
+        self.input.next();
+        if !self.peek().is_some_and(starts_symbol) {
+            return Err(self.error(vec![Token::Ident]));
+        }
+        Ok((self.parse_ident(is_symbol_char)?, offset))
+    }
+
+    /// Whether the `(` coming up starts a list or map rather than a child element, which would
+    /// start with a name
+    fn peek_list_value(&self) -> bool {
+        let mut chars = self
+            .input
+            .clone()
+            .map(|(_, c)| c)
+            .skip(1)
+            .skip_while(|c| c.is_whitespace());
+        matches!(chars.next(), Some('"' | ':' | '$' | ')'))
+    }
 
+    /// `("btn" "btn-primary")`, or `(:color "red" :margin "0 auto")` for a map, where every key
+    /// needs a value
+    fn parse_list_value(&mut self) -> Result<Value, ParseError> {
+        self.input.next();
+        self.skip_whitespace()?;
+
+        if self.peek() != Some(':') {
+            let mut list = vec![];
+            while self.peek() != Some(')') {
+                match self.parse_value()? {
+                    Some(value) => list.push(value),
+                    None => {
+                        let expected = [&VALUE_TOKENS[..], &[Token::CloseParen]].concat();
+                        return Err(self.error(expected));
+                    }
+                }
+                self.skip_whitespace()?;
+            }
+            self.input.next();
+            return Ok(Value::List(list));
+        }
+
+        let mut map = vec![];
+        while self.peek() != Some(')') {
+            if self.peek() != Some(':') {
+                return Err(self.error(vec![Token::Colon, Token::CloseParen]));
+            }
+            self.input.next();
+            let key = self.parse_ident(is_tag_name_char)?;
+            self.skip_whitespace()?;
+            match self.parse_value()? {
+                Some(value) => map.push((key, value)),
+                None => return Err(self.error(VALUE_TOKENS.to_vec())),
+            }
             self.skip_whitespace()?;
         }
+        self.input.next();
+        Ok(Value::Map(map))
+    }
 
-        let mut inner: Vec<Node> = vec![];
-        while {
-            let peek = self.input.peek()?;
-            peek == &'(' || peek == &'"'
-        } {
-            inner.push(self.parse()?);
-            self.skip_whitespace();
+    fn parse_number(&mut self) -> Result<Value, ParseError> {
+        let offset = self.offset();
+        let number = self.parse_ident(is_symbol_char)?;
+        match number.parse::<f64>() {
+            Ok(_) => Ok(Value::Number(number)),
+            Err(_) => Err(self.error_at(offset, ParseErrorKind::InvalidNumber(number))),
         }
+    }
 
-        self.skip_whitespace();
+    /// `(!name ...)` forms that compile to something other than an element, inside the element
+    /// `parent` if it's inside one
+    fn parse_special_form(&mut self, parent: Option<&str>) -> Result<Node, ParseError> {
         self.input.next();
+        let offset = self.offset();
+        let name = self.parse_ident(is_tag_name_char)?;
+        self.skip_whitespace()?;
 
-        Some(Node::Tag {
-            name,
-            attributes,
-            inner,
-        })
+        let node = match name.as_str() {
+            "raw" => Node::Raw(self.parse_string_argument()?),
+            "--" => {
+                let offset = self.offset();
+                let text = self.parse_string_argument()?;
+                if text.contains("-->") || text.contains("--!>") {
+                    return Err(self.error_at(offset, ParseErrorKind::InvalidComment));
+                }
+                Node::Comment(text)
+            }
+            // Like in HTML, `doctype` can be written in any case
+            _ if name.eq_ignore_ascii_case("doctype") && parent.is_some() => {
+                return Err(self.error_at(offset, ParseErrorKind::MisplacedDoctype));
+            }
+            _ if name.eq_ignore_ascii_case("doctype") => match self.peek() {
+                Some('"') => {
+                    let offset = self.offset();
+                    let text = self.parse_string()?;
+                    if text.contains('>') {
+                        return Err(self.error_at(offset, ParseErrorKind::InvalidDoctype));
+                    }
+                    Node::Doctype(text)
+                }
+                Some(c) if c.is_ascii_alphanumeric() => {
+                    Node::Doctype(self.parse_ident(is_tag_name_char)?)
+                }
+                _ => return Err(self.error(vec![Token::Ident, Token::Quote])),
+            },
+            _ => return Err(self.error_at(offset, ParseErrorKind::UnknownForm(name))),
+        };
+
+        self.skip_whitespace()?;
+        if self.peek() != Some(')') {
+            return Err(self.error(vec![Token::CloseParen]));
+        }
+        self.input.next();
+
+        Ok(node)
     }
 
-    /// DAMN YOU `Iterator::take_while` WHY CAN'T YOU BE NORMAL AND PEEK BEFORE CONSUMING
-    fn parse_ident(&mut self) -> Option<String> {
-        if !self.input.peek()?.is_ascii_alphanumeric() {
-            return None;
+    /// `(?xml :version "1.0")`, which takes attributes but no children
+    fn parse_processing_instruction(&mut self) -> Result<Node, ParseError> {
+        self.input.next();
+        let target = self.parse_tag_name()?;
+        self.skip_whitespace()?;
+
+        let mut attributes = vec![];
+        while self.peek() == Some(':') {
+            let offset = self.offset();
+            let (attr, value) = self.parse_attribute()?;
+            if ends_processing_instruction(&value) {
+                let kind = ParseErrorKind::InvalidProcessingInstruction;
+                return Err(self.error_at(offset, kind));
+            }
+            attributes.push((attr, value));
+            self.skip_whitespace()?;
         }
-        let mut attr = String::new();
-        while self.input.peek()?.is_ascii_alphanumeric() {
-            attr.push(self.input.next()?);
+
+        if self.peek() != Some(')') {
+            return Err(self.error(vec![Token::Colon, Token::CloseParen]));
         }
+        self.input.next();
 
-        Some(attr)
+        Ok(Node::ProcessingInstruction { target, attributes })
     }
 
-    fn parse_string(&mut self) -> Option<String> {
-        let mut prev = self.input.next()?;
-        let mut string = String::new();
-        while self.input.peek()? != &'"' || (prev == '\\') {
-            let next = self.input.next()?;
-            string.push(next);
-            prev = next;
+    fn parse_string_argument(&mut self) -> Result<String, ParseError> {
+        if self.peek() != Some('"') {
+            return Err(self.error(vec![Token::Quote]));
+        }
+        self.parse_string()
+    }
+
+    /// Element names start with an ASCII letter or digit, custom elements like `my-widget` included
+    fn parse_tag_name(&mut self) -> Result<String, ParseError> {
+        if !self.peek().is_some_and(|c| c.is_ascii_alphanumeric()) {
+            return Err(self.error(vec![Token::Ident]));
+        }
+        self.parse_ident(is_tag_name_char)
+    }
+
+    /// DAMN YOU `Iterator::take_while` WHY CAN'T YOU BE NORMAL AND PEEK BEFORE CONSUMING
+    fn parse_ident(&mut self, is_ident_char: fn(char) -> bool) -> Result<String, ParseError> {
+        let mut attr = String::new();
+        while let Some(c) = self.peek().filter(|&c| is_ident_char(c)) {
+            attr.push(c);
+            self.input.next();
         }
 
+        if attr.is_empty() {
+            return Err(self.error(vec![Token::Ident]));
+        }
+        Ok(attr)
+    }
+
+    fn parse_string(&mut self) -> Result<String, ParseError> {
+        let start = self.offset();
         self.input.next();
-        string = string.replace("\\\"", "\"");
-        Some(string)
+
+        let mut string = String::new();
+        loop {
+            let offset = self.offset();
+            let c = match self.next() {
+                Some('"') => return Ok(string),
+                Some('\\') => match self.next() {
+                    Some('\\') => '\\',
+                    Some('"') => '"',
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('u') => self.parse_unicode_escape(offset)?,
+                    Some(c) => return Err(self.error_at(offset, ParseErrorKind::UnknownEscape(c))),
+                    None => return Err(self.error_at(start, ParseErrorKind::UnterminatedString)),
+                },
+                Some(c) => c,
+                None => return Err(self.error_at(start, ParseErrorKind::UnterminatedString)),
+            };
+            string.push(c);
+        }
     }
 
-    fn skip_whitespace(&mut self) -> Option<()> {
-        while self.input.peek()?.is_whitespace() {
+    /// The `{1F600}` part of `\u{1F600}`, `offset` being where the backslash is
+    fn parse_unicode_escape(&mut self, offset: usize) -> Result<char, ParseError> {
+        if self.next() != Some('{') {
+            return Err(self.error_at(offset, ParseErrorKind::InvalidUnicodeEscape));
+        }
+        let mut hex = String::new();
+        while let Some(c) = self.peek().filter(char::is_ascii_hexdigit) {
+            hex.push(c);
             self.input.next();
         }
-        Some(())
+        if self.next() != Some('}') || hex.is_empty() || hex.len() > 6 {
+            return Err(self.error_at(offset, ParseErrorKind::InvalidUnicodeEscape));
+        }
+
+        u32::from_str_radix(&hex, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| self.error_at(offset, ParseErrorKind::InvalidUnicodeEscape))
     }
-}
 
-impl fmt::Display for Node {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "{}",
-            match self {
-                Self::Text(s) => s.to_string(),
-                Self::Tag {
-                    name,
-                    attributes,
-                    inner,
-                } => {
-                    format!(
-                        "<{}{}>{}</{}>",
-                        name,
-                        fmt_attrs(attributes),
-                        inner
-                            .iter()
-                            .map(ToString::to_string)
-                            .collect::<Vec<_>>()
-                            .join(""),
-                        name
-                    )
+    /// Skip whitespace, `;` line comments, `#| |#` block comments and forms discarded with `#_`
+    fn skip_whitespace(&mut self) -> Result<(), ParseError> {
+        loop {
+            match (self.peek(), self.peek_second()) {
+                (Some(c), _) if c.is_whitespace() => {
+                    self.input.next();
+                }
+                (Some(';'), _) => while self.next().is_some_and(|c| c != '\n') {},
+                (Some('#'), Some('|')) => self.skip_block_comment()?,
+                (Some('#'), Some('_')) => {
+                    self.input.next();
+                    self.input.next();
+                    self.nested(|parser| {
+                        parser.skip_whitespace()?;
+                        if parser.peek() == Some(':') {
+                            parser.parse_attribute().map(drop)
+                        } else {
+                            parser.parse().map(drop)
+                        }
+                    })?;
                 }
+                _ => return Ok(()),
             }
-        )
+        }
     }
-}
 
-impl Node {
-    pub fn pretty_print(&self, depth: usize) -> String {
-        match self {
-            Self::Text(s) => format!("{}{}", "\t".repeat(depth), s),
-            Self::Tag {
-                name,
-                attributes,
-                inner,
-            } => {
-                let not_empty = !inner.is_empty();
-                format!(
-                    "{}<{}{}>{}{}{}{}</{}>",
-                    "\t".repeat(depth),
-                    name,
-                    fmt_attrs(attributes),
-                    if not_empty { "\n" } else { "" },
-                    inner
-                        .iter()
-                        .map(|node| node.pretty_print(depth + 1))
-                        .collect::<Vec<_>>()
-                        .join("\n"),
-                    if not_empty { "\n" } else { "" },
-                    if not_empty {
-                        "\t".repeat(depth)
-                    } else {
-                        "".to_string()
-                    },
-                    name
-                )
+    /// Block comments nest, so `#| #| |# |#` is one comment
+    fn skip_block_comment(&mut self) -> Result<(), ParseError> {
+        let start = self.offset();
+        let mut depth = 0;
+        loop {
+            match (self.next(), self.peek()) {
+                (Some('#'), Some('|')) => {
+                    self.input.next();
+                    depth += 1;
+                }
+                (Some('|'), Some('#')) => {
+                    self.input.next();
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(());
+                    }
+                }
+                (Some(_), _) => {}
+                (None, _) => return Err(self.error_at(start, ParseErrorKind::UnterminatedComment)),
             }
         }
     }
+
+    /// Run `parse` a level deeper, failing if that's deeper than `MAX_DEPTH`
+    fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, ParseError>,
+    ) -> Result<T, ParseError> {
+        if self.depth == MAX_DEPTH {
+            let offset = self.offset();
+            return Err(self.error_at(offset, ParseErrorKind::TooDeep));
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.input.peek().map(|&(_, c)| c)
+    }
+
+    fn peek_second(&self) -> Option<char> {
+        self.input.clone().nth(1).map(|(_, c)| c)
+    }
+
+    fn next(&mut self) -> Option<char> {
+        self.input.next().map(|(_, c)| c)
+    }
+
+    fn offset(&mut self) -> usize {
+        self.input
+            .peek()
+            .map_or(self.source.len(), |&(offset, _)| offset)
+    }
+
+    /// Error at the current position, with whatever is there as the found token
+    fn error(&mut self, expected: Vec<Token>) -> ParseError {
+        let offset = self.offset();
+        let found = self.peek();
+        self.error_at(offset, ParseErrorKind::Unexpected { expected, found })
+    }
+
+    fn error_at(&self, offset: usize, kind: ParseErrorKind) -> ParseError {
+        ParseError::new(self.source, offset, kind)
+    }
 }
 
-fn fmt_attrs(attrs: &[(String, String)]) -> String {
-    attrs
-        .iter()
-        .map(|(attr, val)| format!(" {}=\"{}\"", attr, val))
-        .collect::<Vec<_>>()
-        .join("")
+/// Whether a processing instruction's attribute would end it early, as attribute values aren't
+/// escaped enough to stop that
+pub(crate) fn ends_processing_instruction(value: &Value) -> bool {
+    value.as_text().is_some_and(|text| text.contains("?>"))
+}
+
+/// Letters and digits (not just ASCII, custom element names allow more) plus `-` and `_`. `.` and
+/// `#` start class and id shorthand instead
+pub(crate) fn is_tag_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '-' || c == '_'
+}
+
+/// HTML allows almost anything in an attribute name (`data-id`, `xlink:href`, `@click`), so only
+/// exclude what HTML forbids and the parentheses that delimit forms
+pub(crate) fn is_attr_name_char(c: char) -> bool {
+    !(c.is_whitespace() || c.is_control() || matches!(c, '"' | '\'' | '>' | '/' | '=' | '(' | ')'))
+}
+
+/// Bare words and the names of variables start with a letter or `_`
+fn starts_symbol(c: char) -> bool {
+    c.is_alphabetic() || c == '_'
+}
+
+/// Whether a child, which is an element, a string or a variable, could start with `c`
+fn starts_node(c: char) -> bool {
+    c == '(' || c == '"' || c == '$' || starts_symbol(c)
+}
+
+/// Bare attribute values end where a string, form or comment could start, so `text/css` is one
+pub(crate) fn is_symbol_char(c: char) -> bool {
+    !(c.is_whitespace() || c.is_control() || matches!(c, '"' | '(' | ')' | ';'))
 }
 
 #[cfg(test)]
@@ -219,4 +918,553 @@ mod tests {
         let parsed = Parser::new(test).parse().unwrap();
         println!("Input: {}\nOutput:\n{}", test, parsed);
     }
+
+    #[test]
+    fn error_location() {
+        let test = "(html\n\t(p \"hi\" 9))";
+        let err = Parser::new(test).parse().unwrap_err();
+        assert_eq!((err.offset, err.line, err.column), (15, 2, 10));
+        assert_eq!(
+            err.kind,
+            ParseErrorKind::Unexpected {
+                expected: vec![
+                    Token::OpenParen,
+                    Token::Quote,
+                    Token::Ident,
+                    Token::Dollar,
+                    Token::CloseParen
+                ],
+                found: Some('9')
+            }
+        );
+    }
+
+    #[test]
+    fn error_end_of_input() {
+        let test = r#"(div :class"#;
+        let err = Parser::new(test).parse().unwrap_err();
+        assert_eq!((err.line, err.column), (1, 12));
+        assert_eq!(
+            err.kind,
+            ParseErrorKind::Unexpected {
+                expected: vec![
+                    Token::Colon,
+                    Token::OpenParen,
+                    Token::Quote,
+                    Token::Ident,
+                    Token::Dollar,
+                    Token::CloseParen
+                ],
+                found: None
+            }
+        );
+    }
+
+    #[test]
+    fn malformed() {
+        let tests = [
+            "",
+            " ",
+            "-html",
+            ")",
+            "; comment",
+            "(",
+            "()",
+            "(html",
+            "(p :",
+            "(p :width 12px)",
+            "(p \"unterminated",
+            "(p ( ))",
+            "\"",
+            "(p :(",
+        ];
+        for test in tests.iter() {
+            let result = Parser::new(test).parse();
+            println!("Input: {}\nOutput: {:?}", test, result);
+            assert!(result.is_err());
+        }
+    }
+
+    #[test]
+    fn deeply_nested() {
+        let test = format!("{}{}", "(a ".repeat(MAX_DEPTH), ")".repeat(MAX_DEPTH));
+        assert!(Parser::new(&test).parse().is_ok());
+
+        for test in [
+            "(a ".repeat(20000),
+            "#_".repeat(20000),
+            format!("(p :style {}", "(:a ".repeat(20000)),
+        ] {
+            let err = Parser::new(&test).parse_document().unwrap_err();
+            assert_eq!(err.kind, ParseErrorKind::TooDeep);
+            assert!(err.offset < test.len());
+        }
+        let err = Parser::new(&"(a ".repeat(20000)).parse().unwrap_err();
+        assert_eq!(err.column, MAX_DEPTH * 3 + 1);
+    }
+
+    #[test]
+    fn leading_whitespace() {
+        let test = "\n  (html)";
+        let parsed = Parser::new(test).parse().unwrap();
+        println!("Input: {}\nOutput:\n{}", test, parsed);
+    }
+
+    #[test]
+    fn document() {
+        let test = r#"(p "one") "two" (p "three")"#;
+        let parsed = Parser::new(test).parse_document().unwrap();
+        assert_eq!(parsed.nodes.len(), 3);
+        assert_eq!(parsed.to_string(), "<p>one</p>two<p>three</p>");
+    }
+
+    #[test]
+    fn document_trailing_garbage() {
+        let test = "(html)\n(body))";
+        let err = Parser::new(test).parse_document().unwrap_err();
+        assert_eq!((err.line, err.column), (2, 7));
+        assert!(matches!(
+            err.kind,
+            ParseErrorKind::Unexpected {
+                found: Some(')'),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn void_elements() {
+        let test = r#"(head (meta :charset "UTF-8") (link :rel "icon") (title "hi"))"#;
+        let parsed = Parser::new(test).parse().unwrap();
+        assert_eq!(
+            parsed.to_string(),
+            r#"<head><meta charset="UTF-8"><link rel="icon"><title>hi</title></head>"#
+        );
+    }
+
+    #[test]
+    fn void_element_children() {
+        let test = r#"(br "text")"#;
+        let err = Parser::new(test).parse().unwrap_err();
+        assert_eq!(err.column, 5);
+        assert_eq!(
+            err.kind,
+            ParseErrorKind::VoidElementChildren("br".to_string())
+        );
+    }
+
+    #[test]
+    fn escaping() {
+        let test = r#"(p :title "say \"hi\" & go" "a < b & c")"#;
+        let parsed = Parser::new(test).parse().unwrap();
+        assert_eq!(
+            parsed.to_string(),
+            r#"<p title="say &quot;hi&quot; &amp; go">a &lt; b &amp; c</p>"#
+        );
+    }
+
+    #[test]
+    fn raw() {
+        let test = r#"(div (!raw "<b>bold</b>") (script "if (a && b < c) {}"))"#;
+        let parsed = Parser::new(test).parse().unwrap();
+        assert_eq!(
+            parsed.to_string(),
+            "<div><b>bold</b><script>if (a && b < c) {}</script></div>"
+        );
+    }
+
+    #[test]
+    fn raw_text_end() {
+        for (test, column, name) in [
+            (r#"(script "</script><b>")"#, 9, "script"),
+            (r#"(STYLE "a {}" "</Style >")"#, 15, "STYLE"),
+            (r#"(div (script (let ((x 1)) "x</script>")))"#, 27, "script"),
+            (r#"(script "</scr" "ipt>")"#, 17, "script"),
+        ] {
+            let err = Parser::new(test).parse().unwrap_err();
+            assert_eq!(err.column, column);
+            assert_eq!(err.kind, ParseErrorKind::RawTextEnd(name.to_string()));
+        }
+
+        let test =
+            r#"(div "</script>" (script "a </scrip t" "</scr" (b) "ipt>") (!raw "</script>"))"#;
+        assert!(Parser::new(test).parse().is_ok());
+    }
+
+    #[test]
+    fn unknown_form() {
+        let test = r#"(!nope "x")"#;
+        let err = Parser::new(test).parse().unwrap_err();
+        assert_eq!(err.column, 3);
+        assert_eq!(err.kind, ParseErrorKind::UnknownForm("nope".to_string()));
+    }
+
+    #[test]
+    fn custom_element_names() {
+        let test = r#"(my-widget (x_y "a") (h1 "b"))"#;
+        let parsed = Parser::new(test).parse().unwrap();
+        assert_eq!(
+            parsed.to_string(),
+            "<my-widget><x_y>a</x_y><h1>b</h1></my-widget>"
+        );
+    }
+
+    #[test]
+    fn data_and_aria_attributes() {
+        let test = r#"(div :data-id "1" :aria-label "close" :hx-get "/a")"#;
+        let parsed = Parser::new(test).parse().unwrap();
+        assert_eq!(
+            parsed.to_string(),
+            r#"<div data-id="1" aria-label="close" hx-get="/a"></div>"#
+        );
+    }
+
+    #[test]
+    fn namespaced_attributes() {
+        let test = r##"(use :xlink:href "#icon" :xml:lang "en")"##;
+        let parsed = Parser::new(test).parse().unwrap();
+        assert_eq!(
+            parsed.to_string(),
+            r##"<use xlink:href="#icon" xml:lang="en"></use>"##
+        );
+    }
+
+    #[test]
+    fn framework_attributes() {
+        let test = r##"(button :@click "go()" :x-on:click.prevent "go()" :#ref "")"##;
+        let parsed = Parser::new(test).parse().unwrap();
+        assert_eq!(
+            parsed.to_string(),
+            r##"<button @click="go()" x-on:click.prevent="go()" #ref=""></button>"##
+        );
+    }
+
+    #[test]
+    fn invalid_names() {
+        for test in ["(-div)", "(div :a=b \"\")", "(div :/ \"\")"].iter() {
+            assert!(Parser::new(test).parse().is_err(), "{}", test);
+        }
+    }
+
+    #[test]
+    fn string_escapes() {
+        let test = r#"(pre "C:\\" "\"q\"\ttab\nline\u{e9}\u{1F600}")"#;
+        let parsed = Parser::new(test).parse().unwrap();
+        assert_eq!(
+            parsed.to_string(),
+            "<pre>C:\\\"q\"\ttab\nline\u{e9}\u{1F600}</pre>"
+        );
+    }
+
+    #[test]
+    fn unterminated_string() {
+        let test = "(p\n  \"abc\\\")";
+        let err = Parser::new(test).parse().unwrap_err();
+        assert_eq!((err.line, err.column), (2, 3));
+        assert_eq!(err.kind, ParseErrorKind::UnterminatedString);
+    }
+
+    #[test]
+    fn invalid_escapes() {
+        let err = Parser::new(r#"(p "a\qb")"#).parse().unwrap_err();
+        assert_eq!(err.column, 6);
+        assert_eq!(err.kind, ParseErrorKind::UnknownEscape('q'));
+
+        for test in [
+            r#""\u{}""#,
+            r#""\u{D800}""#,
+            r#""\u{1234567}""#,
+            r#""\u41""#,
+        ]
+        .iter()
+        {
+            let err = Parser::new(test).parse().unwrap_err();
+            assert_eq!(err.column, 2);
+            assert_eq!(err.kind, ParseErrorKind::InvalidUnicodeEscape);
+        }
+    }
+
+    #[test]
+    fn comments() {
+        let test = r#"; the page
+(ul :class "a" ; first attribute
+    #| a #| nested |# block |#
+    :id "b"
+    (li "one") ; trailing
+    #_(li "two")
+    #_ :hidden ""
+    (li "three" #_"four")) ; end"#;
+        let parsed = Parser::new(test).parse_document().unwrap();
+        assert_eq!(
+            parsed.to_string(),
+            r#"<ul class="a" id="b"><li>one</li><li>three</li></ul>"#
+        );
+    }
+
+    #[test]
+    fn unterminated_comment() {
+        let test = "(p #| never |# #| closed)";
+        let err = Parser::new(test).parse().unwrap_err();
+        assert_eq!(err.column, 16);
+        assert_eq!(err.kind, ParseErrorKind::UnterminatedComment);
+    }
+
+    #[test]
+    fn html_comment() {
+        let test = r#"(body (!-- "rendered") ; not rendered
+    (p "text"))"#;
+        let parsed = Parser::new(test).parse().unwrap();
+        assert_eq!(
+            parsed.to_string(),
+            "<body><!-- rendered --><p>text</p></body>"
+        );
+
+        let err = Parser::new(r#"(!-- "a --> b")"#).parse().unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::InvalidComment);
+    }
+
+    #[test]
+    fn doctype() {
+        let test = r#"(!doctype html) (html)"#;
+        let parsed = Parser::new(test).parse_document().unwrap();
+        assert_eq!(parsed.to_string(), "<!DOCTYPE html><html></html>");
+        let test = r#"(let ((x 1)) (!Doctype html))"#;
+        let parsed = Parser::new(test).parse_document().unwrap();
+        assert_eq!(
+            parsed.evaluate(test).unwrap().to_string(),
+            "<!DOCTYPE html>"
+        );
+
+        let test = r#"(!DOCTYPE "html PUBLIC \"-//W3C//DTD HTML 4.01//EN\"")"#;
+        let parsed = Parser::new(test).parse().unwrap();
+        assert_eq!(
+            parsed.to_string(),
+            r#"<!DOCTYPE html PUBLIC "-//W3C//DTD HTML 4.01//EN">"#
+        );
+
+        let err = Parser::new(r#"(!doctype "html>x")"#).parse().unwrap_err();
+        assert_eq!(err.column, 11);
+        assert_eq!(err.kind, ParseErrorKind::InvalidDoctype);
+
+        let err = Parser::new(r#"(p (!doctype html))"#).parse().unwrap_err();
+        assert_eq!(err.column, 6);
+        assert_eq!(err.kind, ParseErrorKind::MisplacedDoctype);
+    }
+
+    #[test]
+    fn processing_instruction() {
+        let test = r#"(?xml :version "1.0" :encoding "UTF-8") (svg)"#;
+        let parsed = Parser::new(test).parse_document().unwrap();
+        assert_eq!(
+            parsed.to_string(),
+            r#"<?xml version="1.0" encoding="UTF-8"?><svg></svg>"#
+        );
+
+        let err = Parser::new(r#"(?xml (p))"#).parse().unwrap_err();
+        assert_eq!(
+            err.kind,
+            ParseErrorKind::Unexpected {
+                expected: vec![Token::Colon, Token::CloseParen],
+                found: Some('(')
+            }
+        );
+
+        for (test, column) in [(r#"(?xml :a "?>")"#, 7), (r#"(?xml :v ("a" "b?>"))"#, 7)] {
+            let err = Parser::new(test).parse().unwrap_err();
+            assert_eq!(err.column, column);
+            assert_eq!(err.kind, ParseErrorKind::InvalidProcessingInstruction);
+        }
+    }
+
+    #[test]
+    fn valueless_attributes() {
+        let test = r#"(div (script :defer :src "a.js") (script :src "a.js" :defer) (button :disabled (span "x")) (p :hidden "text"))"#;
+        let parsed = Parser::new(test).parse().unwrap();
+        assert_eq!(
+            parsed.to_string(),
+            r#"<div><script defer src="a.js"></script><script src="a.js" defer></script><button disabled><span>x</span></button><p hidden="text"></p></div>"#
+        );
+    }
+
+    #[test]
+    fn typed_values() {
+        let test = r#"(input :type text :maxlength 10 :step 0.5 :min -2 :required true :disabled false :style text/css)"#;
+        let parsed = Parser::new(test).parse().unwrap();
+        match &parsed {
+            Node::Tag { attributes, .. } => assert_eq!(
+                attributes
+                    .iter()
+                    .map(|(_, v)| v.clone())
+                    .collect::<Vec<_>>(),
+                vec![
+                    Value::Symbol("text".to_string()),
+                    Value::Number("10".to_string()),
+                    Value::Number("0.5".to_string()),
+                    Value::Number("-2".to_string()),
+                    Value::Bool(true),
+                    Value::Bool(false),
+                    Value::Symbol("text/css".to_string()),
+                ]
+            ),
+            node => panic!("expected a tag, got {:?}", node),
+        }
+        assert_eq!(
+            parsed.to_string(),
+            r#"<input type="text" maxlength="10" step="0.5" min="-2" required style="text/css">"#
+        );
+    }
+
+    #[test]
+    fn invalid_number() {
+        let err = Parser::new("(img :width 100px)").parse().unwrap_err();
+        assert_eq!(err.column, 13);
+        assert_eq!(err.kind, ParseErrorKind::InvalidNumber("100px".to_string()));
+    }
+
+    #[test]
+    fn numbers_keep_their_text() {
+        let test = r#"(?xml :version 1.0) (input :value 007 :step 1e30 :min -0.5 :max +2.50)"#;
+        let document = Parser::new(test).parse_document().unwrap();
+        assert_eq!(
+            document.to_string(),
+            r#"<?xml version="1.0"?><input value="007" step="1e30" min="-0.5" max="+2.50">"#
+        );
+    }
+
+    #[test]
+    fn shorthand() {
+        let test = r#"(div.card.shadow#main (p.lead "a") (span#x.y :class "z" "b") (my-el.a :class false) (b#|c|# "d"))"#;
+        assert_eq!(
+            crate::compile(test, Default::default()).unwrap(),
+            r#"<div class="card shadow" id="main"><p class="lead">a</p><span id="x" class="y z">b</span><my-el class="a"></my-el><b>d</b></div>"#
+        );
+    }
+
+    #[test]
+    fn duplicate_attributes() {
+        for (test, column, attr) in [
+            (r#"(div#a :id "b")"#, 8, "id"),
+            ("(div#a#b)", 7, "id"),
+            (r#"(a :href "x" :href "y")"#, 14, "href"),
+            (r#"(p :Id "a" :id "b")"#, 12, "id"),
+            (r#"(p#a :ID "b")"#, 6, "ID"),
+        ] {
+            let err = Parser::new(test).parse().unwrap_err();
+            assert_eq!(err.column, column);
+            assert_eq!(
+                err.kind,
+                ParseErrorKind::DuplicateAttribute(attr.to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn list_and_map_values() {
+        let test = r#"(button :class ("btn" "btn-primary" #_ "gone") :style (:color "red" :margin "0 auto" :width 10) :data-x () :disabled (span "go"))"#;
+        let parsed = Parser::new(test).parse().unwrap();
+        assert_eq!(
+            parsed.to_string(),
+            r#"<button class="btn btn-primary" style="color: red; margin: 0 auto; width: 10" data-x="" disabled><span>go</span></button>"#
+        );
+
+        // A list has to start with a string or variable, or else it's a child element
+        let test = r#"(p :class ("btn" primary 2) :style ( :color red) "x") (p :class (x "a"))"#;
+        let document = Parser::new(test).parse_document().unwrap();
+        assert_eq!(
+            document.to_string(),
+            r#"<p class="btn primary 2" style="color: red">x</p><p class><x>a</x></p>"#
+        );
+
+        for (test, column) in [
+            (r#"(p :style (:color))"#, 18),
+            (r#"(p :style (:color "red" color))"#, 25),
+            (r#"(p :class ("a" (b)))"#, 16),
+            (r#"(p :class ("a")"#, 16),
+        ] {
+            let err = Parser::new(test).parse().unwrap_err();
+            assert_eq!(err.column, column, "{}", test);
+        }
+    }
+
+    #[test]
+    fn merged_attributes() {
+        let test = r#"(p.a :class ("b" "c") :style "color: red;" :style (:margin 0) "text")"#;
+        assert_eq!(
+            crate::compile(test, Default::default()).unwrap(),
+            r#"<p class="a b c" style="color: red; margin: 0">text</p>"#
+        );
+    }
+
+    #[test]
+    fn def_and_let() {
+        let test = r#"(def title "Home") (let ((x 1) (y (:color $x))) (p :style $y $x "!")) (p title) (def.x)"#;
+        let document = Parser::new(test).parse_document().unwrap();
+        match &document.nodes[..] {
+            [Node::Def {
+                name,
+                value,
+                offset: 0,
+            }, Node::Let { bindings, inner }, Node::Tag { inner: text, .. }, Node::Tag { name: def, .. }] =>
+            {
+                assert_eq!(
+                    (name.as_str(), value),
+                    ("title", &Value::String("Home".to_string()))
+                );
+                assert_eq!(
+                    bindings,
+                    &vec![
+                        ("x".to_string(), Value::Number("1".to_string())),
+                        (
+                            "y".to_string(),
+                            Value::Map(vec![(
+                                "color".to_string(),
+                                Value::Variable {
+                                    name: "x".to_string(),
+                                    offset: 42
+                                }
+                            )])
+                        ),
+                    ]
+                );
+                assert_eq!(inner.len(), 1);
+                assert!(
+                    matches!(&text[..], [Node::Variable { name, offset: 73 }] if name == "title")
+                );
+                assert_eq!(def, "def");
+            }
+            nodes => panic!("unexpected nodes {:?}", nodes),
+        }
+
+        for (test, column) in [
+            ("(def)", 5),
+            ("(def x)", 7),
+            (r#"(def x "a" "b")"#, 12),
+            (r#"(let (x "a") x)"#, 7),
+            (r#"(let ((x "a")) :class x)"#, 16),
+            ("(p $)", 5),
+            ("(p :class $1)", 12),
+        ] {
+            let err = Parser::new(test).parse().unwrap_err();
+            assert_eq!(err.column, column, "{}", test);
+        }
+
+        let children = [&CHILD_TOKENS[..], &[Token::CloseParen]].concat();
+        let list = [&VALUE_TOKENS[..], &[Token::CloseParen]].concat();
+        for (test, expected) in [
+            ("9", &CHILD_TOKENS[..]),
+            ("(p \"a\" 9", &children[..]),
+            ("(let () 9", &children[..]),
+            ("(def x ]", &VALUE_TOKENS[..]),
+            ("(let ((x ]", &VALUE_TOKENS[..]),
+            ("(p :class (\"a\" ]", &list[..]),
+            ("(p :style (:color ]", &VALUE_TOKENS[..]),
+        ] {
+            let err = Parser::new(test).parse().unwrap_err();
+            let kind = ParseErrorKind::Unexpected {
+                expected: expected.to_vec(),
+                found: test.chars().last(),
+            };
+            assert_eq!(err.kind, kind, "{}", test);
+        }
+    }
 }
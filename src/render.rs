@@ -0,0 +1,770 @@
+use crate::parser::{Document, Node, Value};
+use core::fmt::{self, Write};
+use std::io;
+
+/// How prettified HTML is laid out
+#[derive(Debug, Clone)]
+pub struct FormatOptions {
+    /// Added once per level of nesting
+    pub indent: String,
+    /// Lines are kept within this many columns where possible, counting tabs as 4
+    pub max_width: Option<usize>,
+    pub wrap_attributes: WrapAttributes,
+    /// End the output with a newline
+    pub trailing_newline: bool,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        Self {
+            indent: "\t".to_string(),
+            max_width: None,
+            wrap_attributes: WrapAttributes::Never,
+            trailing_newline: false,
+        }
+    }
+}
+
+/// When to put each of an element's attributes on its own line
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WrapAttributes {
+    Never,
+    /// Only if the start tag wouldn't fit in `max_width`
+    Auto,
+    /// Whenever there's more than one
+    Always,
+}
+
+impl FormatOptions {
+    /// Whether everything `write` writes, starting at the beginning of a line, keeps within
+    /// `max_width`. Nothing is buffered, so this is cheap even for a big subtree
+    fn fits(&self, write: impl FnOnce(&mut Measure) -> fmt::Result) -> bool {
+        match self.max_width {
+            Some(max_width) => write(&mut Measure {
+                column: 0,
+                max_width,
+            })
+            .is_ok(),
+            None => true,
+        }
+    }
+}
+
+/// Counts columns instead of writing anything, failing as soon as a line gets too wide
+struct Measure {
+    column: usize,
+    max_width: usize,
+}
+
+impl fmt::Write for Measure {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            self.column = if c == '\n' { 0 } else { self.column + width(c) };
+            if self.column > self.max_width {
+                return Err(fmt::Error);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn width(c: char) -> usize {
+    if c == '\t' {
+        4
+    } else {
+        1
+    }
+}
+
+/// Lets the renderer write to an `io::Write`, holding on to the io error as `fmt::Error` can't
+pub(crate) struct IoWriter<W> {
+    pub(crate) inner: W,
+    pub(crate) error: Option<io::Error>,
+}
+
+impl<W: io::Write> fmt::Write for IoWriter<W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.inner.write_all(s.as_bytes()).map_err(|e| {
+            self.error = Some(e);
+            fmt::Error
+        })
+    }
+}
+
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.render(f)
+    }
+}
+
+impl Node {
+    /// Write the node as HTML, all on one line. This fails if the node has variables, `def`s or
+    /// `let`s in it, which only [`Node::evaluate`] can replace, so `to_string` panics on them
+    pub fn render<W: fmt::Write>(&self, out: &mut W) -> fmt::Result {
+        match self {
+            Self::Text(s) => write_escaped(out, s, escape_text),
+            Self::Raw(s) => out.write_str(s),
+            Self::Comment(s) => write!(out, "<!-- {} -->", s),
+            Self::Doctype(s) => write!(out, "<!DOCTYPE {}>", s),
+            Self::ProcessingInstruction { target, attributes } => {
+                write!(out, "<?{}", target)?;
+                write_attrs(out, attributes)?;
+                out.write_str("?>")
+            }
+            Self::Tag {
+                name,
+                attributes,
+                inner,
+            } => {
+                write!(out, "<{}", name)?;
+                write_attrs(out, attributes)?;
+                out.write_char('>')?;
+                if !is_void_element(name) {
+                    write_inner(out, name, inner)?;
+                    write!(out, "</{}>", name)?;
+                }
+                Ok(())
+            }
+            // Only `Document::evaluate` knows what these stand for
+            Self::Variable { .. } | Self::Def { .. } | Self::Let { .. } => Err(fmt::Error),
+        }
+    }
+
+    /// [`Node::pretty_print_with`] the default [`FormatOptions`]
+    pub fn pretty_print(&self, depth: usize) -> Result<String, fmt::Error> {
+        self.pretty_print_with(&FormatOptions::default(), depth)
+    }
+
+    /// [`Node::render_pretty`] to a `String`, which like it fails if the node hasn't been evaluated
+    pub fn pretty_print_with(
+        &self,
+        format: &FormatOptions,
+        depth: usize,
+    ) -> Result<String, fmt::Error> {
+        let mut html = String::new();
+        self.render_pretty(&mut html, format, depth)?;
+        Ok(html)
+    }
+
+    /// Write the node as HTML laid out over multiple lines, but only where that can't change how
+    /// the page renders: elements go on their own lines only when every sibling is block level,
+    /// and text is only broken where it already has whitespace, so inline elements and the
+    /// contents of `pre` and friends are written exactly as they are
+    pub fn render_pretty<W: fmt::Write>(
+        &self,
+        out: &mut W,
+        format: &FormatOptions,
+        depth: usize,
+    ) -> fmt::Result {
+        let (name, attributes, inner) = match self {
+            Self::Tag {
+                name,
+                attributes,
+                inner,
+            } => (name, attributes, inner),
+            node => {
+                write_indent(out, format, depth)?;
+                return node.render(out);
+            }
+        };
+
+        write_start_tag(out, name, attributes, format, depth)?;
+        if is_void_element(name) {
+            return Ok(());
+        }
+
+        let block = is_block_element(name) && !is_preformatted_element(name) && !inner.is_empty();
+        if block && inner.iter().all(Node::is_block_level) {
+            for node in inner {
+                out.write_char('\n')?;
+                node.render_pretty(out, format, depth + 1)?;
+            }
+            out.write_char('\n')?;
+            write_indent(out, format, depth)?;
+        } else if block && !self.fits_on_one_line(format, depth) {
+            // Whitespace at the start and end of a block's content isn't rendered, so it's safe to
+            // move the content onto its own lines
+            out.write_char('\n')?;
+            Filler::new(out, format, depth + 1).fill(inner)?;
+            out.write_char('\n')?;
+            write_indent(out, format, depth)?;
+        } else {
+            write_inner(out, name, inner)?;
+        }
+        write!(out, "</{}>", name)
+    }
+
+    /// Whether an element with inline content can be written as-is within `max_width`
+    fn fits_on_one_line(&self, format: &FormatOptions, depth: usize) -> bool {
+        match self {
+            Self::Tag {
+                name,
+                attributes,
+                inner,
+            } => format.fits(|out| {
+                write_start_tag(out, name, attributes, format, depth)?;
+                write_inner(out, name, inner)?;
+                write!(out, "</{}>", name)
+            }),
+            node => format.fits(|out| node.render(out)),
+        }
+    }
+
+    /// Whether whitespace around the node is insignificant, as between two `<div>`s
+    pub(crate) fn is_block_level(&self) -> bool {
+        match self {
+            Self::Text(_) | Self::Raw(_) | Self::Variable { .. } => false,
+            Self::Comment(_)
+            | Self::Doctype(_)
+            | Self::ProcessingInstruction { .. }
+            | Self::Def { .. } => true,
+            Self::Tag { name, .. } => is_block_element(name),
+            Self::Let { inner, .. } => inner.iter().all(Node::is_block_level),
+        }
+    }
+}
+
+/// The indented start tag, with its attributes on their own lines if they need wrapping
+fn write_start_tag<W: fmt::Write>(
+    out: &mut W,
+    name: &str,
+    attributes: &[(String, Value)],
+    format: &FormatOptions,
+    depth: usize,
+) -> fmt::Result {
+    let wrap = match format.wrap_attributes {
+        WrapAttributes::Never => false,
+        WrapAttributes::Auto => !format.fits(|out| {
+            write_indent(out, format, depth)?;
+            write!(out, "<{}", name)?;
+            write_attrs(out, attributes)?;
+            out.write_char('>')
+        }),
+        WrapAttributes::Always => attributes.iter().filter(is_rendered).count() > 1,
+    };
+
+    write_indent(out, format, depth)?;
+    write!(out, "<{}", name)?;
+    if wrap {
+        for attr in attributes.iter().filter(is_rendered) {
+            out.write_char('\n')?;
+            write_indent(out, format, depth + 1)?;
+            write_attr(out, attr)?;
+        }
+    } else {
+        write_attrs(out, attributes)?;
+    }
+    out.write_char('>')
+}
+
+/// Writes inline content a word at a time, starting a new line only where the text has
+/// whitespace and the next word wouldn't fit
+struct Filler<'a, W> {
+    out: &'a mut W,
+    format: &'a FormatOptions,
+    depth: usize,
+    /// What's been rendered since the last whitespace, which can't be split
+    word: String,
+    /// Whether there was whitespace before `word`
+    space: bool,
+    /// `None` until something's been written
+    column: Option<usize>,
+}
+
+impl<'a, W: fmt::Write> Filler<'a, W> {
+    fn new(out: &'a mut W, format: &'a FormatOptions, depth: usize) -> Self {
+        Self {
+            out,
+            format,
+            depth,
+            word: String::new(),
+            space: false,
+            column: None,
+        }
+    }
+
+    fn fill(mut self, nodes: &[Node]) -> fmt::Result {
+        self.push_nodes(nodes)?;
+        self.flush()
+    }
+
+    fn push_nodes(&mut self, nodes: &[Node]) -> fmt::Result {
+        for node in nodes {
+            match node {
+                Node::Text(s) => {
+                    for c in s.chars() {
+                        if c.is_ascii_whitespace() {
+                            self.flush()?;
+                            self.space = true;
+                        } else {
+                            match escape_text(c) {
+                                Some(entity) => self.word.push_str(entity),
+                                None => self.word.push(c),
+                            }
+                        }
+                    }
+                }
+                Node::Tag {
+                    name,
+                    attributes,
+                    inner,
+                } if !is_void_element(name) && !is_preformatted_element(name) => {
+                    write!(self.word, "<{}", name)?;
+                    write_attrs(&mut self.word, attributes)?;
+                    self.word.push('>');
+                    self.push_nodes(inner)?;
+                    write!(self.word, "</{}>", name)?;
+                }
+                node => node.render(&mut self.word)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Write out the word, on the current line if it fits or else on the next
+    fn flush(&mut self) -> fmt::Result {
+        if self.word.is_empty() {
+            return Ok(());
+        }
+
+        let indent_width = self.format.indent.chars().map(width).sum::<usize>() * self.depth;
+        let word_width: usize = self
+            .word
+            .chars()
+            .take_while(|&c| c != '\n')
+            .map(width)
+            .sum();
+        let column = match self.column {
+            Some(column) if !self.space => column,
+            Some(column)
+                if self
+                    .format
+                    .max_width
+                    .is_none_or(|max_width| column + 1 + word_width <= max_width) =>
+            {
+                self.out.write_char(' ')?;
+                column + 1
+            }
+            Some(_) => {
+                self.out.write_char('\n')?;
+                write_indent(self.out, self.format, self.depth)?;
+                indent_width
+            }
+            // Leading whitespace isn't rendered, so is dropped along with trailing whitespace
+            None => {
+                write_indent(self.out, self.format, self.depth)?;
+                indent_width
+            }
+        };
+
+        self.out.write_str(&self.word)?;
+        self.column = Some(match self.word.rfind('\n') {
+            Some(newline) => self.word[newline + 1..].chars().map(width).sum(),
+            None => column + word_width,
+        });
+        self.word.clear();
+        self.space = false;
+        Ok(())
+    }
+}
+
+impl fmt::Display for Document {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.render(f)
+    }
+}
+
+impl Document {
+    /// Write every node as HTML, all on one line. Like [`Node::render`] this fails if the
+    /// document hasn't been evaluated
+    pub fn render<W: fmt::Write>(&self, out: &mut W) -> fmt::Result {
+        for node in &self.nodes {
+            node.render(out)?;
+        }
+        Ok(())
+    }
+
+    /// [`Document::pretty_print_with`] the default [`FormatOptions`]
+    pub fn pretty_print(&self, depth: usize) -> Result<String, fmt::Error> {
+        self.pretty_print_with(&FormatOptions::default(), depth)
+    }
+
+    /// [`Document::render_pretty`] to a `String`, which like it fails if the document hasn't been
+    /// evaluated
+    pub fn pretty_print_with(
+        &self,
+        format: &FormatOptions,
+        depth: usize,
+    ) -> Result<String, fmt::Error> {
+        let mut html = String::new();
+        self.render_pretty(&mut html, format, depth)?;
+        Ok(html)
+    }
+
+    /// Write every node as HTML laid out like [`Node::render_pretty`]
+    pub fn render_pretty<W: fmt::Write>(
+        &self,
+        out: &mut W,
+        format: &FormatOptions,
+        depth: usize,
+    ) -> fmt::Result {
+        if self.nodes.iter().all(Node::is_block_level) {
+            for (i, node) in self.nodes.iter().enumerate() {
+                if i > 0 {
+                    out.write_char('\n')?;
+                }
+                node.render_pretty(out, format, depth)?;
+            }
+        } else {
+            write_indent(out, format, depth)?;
+            self.render(out)?;
+        }
+        if format.trailing_newline {
+            out.write_char('\n')?;
+        }
+        Ok(())
+    }
+}
+
+/// Elements that are laid out as blocks, or aren't rendered at all, so whitespace around them
+/// doesn't show. Anything not listed, custom elements included, is treated as inline
+const BLOCK_ELEMENTS: [&str; 58] = [
+    "address",
+    "article",
+    "aside",
+    "base",
+    "blockquote",
+    "body",
+    "caption",
+    "col",
+    "colgroup",
+    "dd",
+    "details",
+    "dialog",
+    "div",
+    "dl",
+    "dt",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "head",
+    "header",
+    "hgroup",
+    "hr",
+    "html",
+    "legend",
+    "li",
+    "link",
+    "main",
+    "menu",
+    "meta",
+    "nav",
+    "noscript",
+    "ol",
+    "optgroup",
+    "option",
+    "p",
+    "pre",
+    "script",
+    "section",
+    "source",
+    "style",
+    "summary",
+    "table",
+    "tbody",
+    "td",
+    "tfoot",
+    "th",
+    "thead",
+    "title",
+    "tr",
+    "ul",
+];
+
+pub(crate) fn is_block_element(name: &str) -> bool {
+    BLOCK_ELEMENTS
+        .iter()
+        .any(|block| block.eq_ignore_ascii_case(name))
+}
+
+/// Elements whose whitespace is significant or which hold code, so are never reformatted
+pub(crate) fn is_preformatted_element(name: &str) -> bool {
+    ["pre", "textarea", "listing", "xmp", "plaintext"]
+        .iter()
+        .any(|pre| pre.eq_ignore_ascii_case(name))
+        || is_raw_text_element(name)
+}
+
+/// Browsers ignore a newline straight after `<pre>`, `<textarea>` or `<listing>`
+pub(crate) fn drops_leading_newline(name: &str) -> bool {
+    ["pre", "textarea", "listing"]
+        .iter()
+        .any(|pre| pre.eq_ignore_ascii_case(name))
+}
+
+/// Elements that can't have content and so are written without an end tag
+const VOID_ELEMENTS: [&str; 14] = [
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+
+pub(crate) fn is_void_element(name: &str) -> bool {
+    VOID_ELEMENTS
+        .iter()
+        .any(|void| void.eq_ignore_ascii_case(name))
+}
+
+/// Elements whose text content is script or CSS rather than HTML, so must not be escaped
+pub(crate) fn is_raw_text_element(name: &str) -> bool {
+    name.eq_ignore_ascii_case("script") || name.eq_ignore_ascii_case("style")
+}
+
+/// Whether `text` would end the raw text element `name` if written in it unescaped
+pub(crate) fn closes_raw_text(name: &str, text: &str) -> bool {
+    is_raw_text_element(name)
+        && text
+            .to_ascii_lowercase()
+            .contains(&format!("</{}", name.to_ascii_lowercase()))
+}
+
+fn escape_text(c: char) -> Option<&'static str> {
+    match c {
+        '&' => Some("&amp;"),
+        '<' => Some("&lt;"),
+        '>' => Some("&gt;"),
+        _ => None,
+    }
+}
+
+fn escape_attr(c: char) -> Option<&'static str> {
+    match c {
+        '&' => Some("&amp;"),
+        '"' => Some("&quot;"),
+        _ => None,
+    }
+}
+
+/// Write `s`, replacing the characters `escape` has an entity for
+fn write_escaped<W: fmt::Write>(
+    out: &mut W,
+    s: &str,
+    escape: fn(char) -> Option<&'static str>,
+) -> fmt::Result {
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        if let Some(entity) = escape(c) {
+            out.write_str(&s[start..i])?;
+            out.write_str(entity)?;
+            start = i + c.len_utf8();
+        }
+    }
+    out.write_str(&s[start..])
+}
+
+fn write_indent<W: fmt::Write>(out: &mut W, format: &FormatOptions, depth: usize) -> fmt::Result {
+    for _ in 0..depth {
+        out.write_str(&format.indent)?;
+    }
+    Ok(())
+}
+
+/// An element's content on one line
+fn write_inner<W: fmt::Write>(out: &mut W, name: &str, inner: &[Node]) -> fmt::Result {
+    // A newline of the content's own has to come after the one browsers drop, wherever the
+    // content actually starts
+    let first = inner
+        .iter()
+        .find(|node| !matches!(node, Node::Text(s) if s.is_empty()));
+    if let Some(Node::Text(s)) = first {
+        if s.starts_with('\n') && drops_leading_newline(name) {
+            out.write_char('\n')?;
+        }
+    }
+    for node in inner {
+        match node {
+            Node::Text(s) if is_raw_text_element(name) => out.write_str(s)?,
+            node => node.render(out)?,
+        }
+    }
+    Ok(())
+}
+
+fn write_attrs<W: fmt::Write>(out: &mut W, attrs: &[(String, Value)]) -> fmt::Result {
+    for attr in attrs.iter().filter(is_rendered) {
+        out.write_char(' ')?;
+        write_attr(out, attr)?;
+    }
+    Ok(())
+}
+
+/// `false` attributes are left out entirely
+fn is_rendered((_, val): &&(String, Value)) -> bool {
+    *val != Value::Bool(false)
+}
+
+/// `name="value"`, or just `name` for `true` attributes
+fn write_attr<W: fmt::Write>(out: &mut W, (attr, val): &(String, Value)) -> fmt::Result {
+    out.write_str(attr)?;
+    match val {
+        Value::String(s) | Value::Symbol(s) => {
+            out.write_str("=\"")?;
+            write_escaped(out, s, escape_attr)?;
+            out.write_char('"')
+        }
+        Value::Number(n) => write!(out, "=\"{}\"", n),
+        Value::Bool(_) => Ok(()),
+        Value::Variable { .. } => Err(fmt::Error),
+        Value::List(_) | Value::Map(_) => {
+            // Only lists and maps with variables in them have no text
+            let text = val.as_text().ok_or(fmt::Error)?;
+            out.write_str("=\"")?;
+            write_escaped(out, &text, escape_attr)?;
+            out.write_char('"')
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Parser;
+
+    fn pretty(input: &str) -> String {
+        Parser::new(input)
+            .parse_document()
+            .unwrap()
+            .pretty_print(0)
+            .unwrap()
+    }
+
+    #[test]
+    fn block_children() {
+        let test = r#"(ul (li "one") (!-- "two") (li (b "three")))"#;
+        assert_eq!(
+            pretty(test),
+            "<ul>\n\t<li>one</li>\n\t<!-- two -->\n\t<li><b>three</b></li>\n</ul>"
+        );
+    }
+
+    #[test]
+    fn inline_children() {
+        let test = r#"(div (p "a " (a :href "/" "link") ", " (span "b")) "text" (div))"#;
+        assert_eq!(
+            pretty(test),
+            r#"<div><p>a <a href="/">link</a>, <span>b</span></p>text<div></div></div>"#
+        );
+    }
+
+    #[test]
+    fn preformatted() {
+        let test = r#"(body (pre (code "fn main() {}") (div)) (style "a {}"))"#;
+        assert_eq!(
+            pretty(test),
+            "<body>\n\t<pre><code>fn main() {}</code><div></div></pre>\n\t<style>a {}</style>\n</body>"
+        );
+        assert_eq!(pretty(r#"(pre "" "\nx")"#), "<pre>\n\nx</pre>");
+        let test = r#"(form (textarea (p) (p)))"#;
+        assert_eq!(
+            pretty(test),
+            "<form><textarea><p></p><p></p></textarea></form>"
+        );
+    }
+
+    #[test]
+    fn unknown_elements_are_inline() {
+        let test = r#"(my-card (div) (div))"#;
+        assert_eq!(pretty(test), "<my-card><div></div><div></div></my-card>");
+        let test = r#"(div (my-card) (div))"#;
+        assert_eq!(pretty(test), "<div><my-card></my-card><div></div></div>");
+    }
+
+    #[test]
+    fn document() {
+        assert_eq!(
+            pretty(r#"(!doctype html) (html (head) (body))"#),
+            "<!DOCTYPE html>\n<html>\n\t<head></head>\n\t<body></body>\n</html>"
+        );
+        assert_eq!(pretty(r#""one" (b "two")"#), "one<b>two</b>");
+    }
+
+    fn pretty_with(input: &str, format: &FormatOptions) -> String {
+        Parser::new(input)
+            .parse_document()
+            .unwrap()
+            .pretty_print_with(format, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn indent_and_trailing_newline() {
+        let format = FormatOptions {
+            indent: "  ".to_string(),
+            trailing_newline: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            pretty_with(r#"(ul (li "a") (li "b"))"#, &format),
+            "<ul>\n  <li>a</li>\n  <li>b</li>\n</ul>\n"
+        );
+    }
+
+    #[test]
+    fn wrap_text() {
+        let format = FormatOptions {
+            indent: "  ".to_string(),
+            max_width: Some(20),
+            ..Default::default()
+        };
+        let test =
+            r#"(div (p "Some text that " (a :href "/x" "won't fit") " on one line.") (p "short"))"#;
+        assert_eq!(
+            pretty_with(test, &format),
+            "<div>\n  <p>\n    Some text that\n    <a href=\"/x\">won't\n    fit</a> on one\n    line.\n  </p>\n  <p>short</p>\n</div>"
+        );
+    }
+
+    #[test]
+    fn wrap_never_breaks_preformatted_or_attributes() {
+        let format = FormatOptions {
+            max_width: Some(10),
+            ..Default::default()
+        };
+        let test = r#"(p (code :class "a b c" "x y") (textarea "one two three") " end")"#;
+        assert_eq!(
+            pretty_with(test, &format),
+            "<p>\n\t<code class=\"a b c\">x\n\ty</code><textarea>one two three</textarea>\n\tend\n</p>"
+        );
+    }
+
+    #[test]
+    fn wrap_attributes() {
+        let test = r#"(head (link :rel "icon" :href "a.png") (base))"#;
+        let always = FormatOptions {
+            wrap_attributes: WrapAttributes::Always,
+            ..Default::default()
+        };
+        assert_eq!(
+            pretty_with(test, &always),
+            "<head>\n\t<link\n\t\trel=\"icon\"\n\t\thref=\"a.png\">\n\t<base>\n</head>"
+        );
+
+        let test = r#"(head (meta :charset "UTF-8") (meta :name "viewport" :content "width=device-width"))"#;
+        let auto = FormatOptions {
+            max_width: Some(40),
+            wrap_attributes: WrapAttributes::Auto,
+            ..Default::default()
+        };
+        assert_eq!(
+            pretty_with(test, &auto),
+            "<head>\n\t<meta charset=\"UTF-8\">\n\t<meta\n\t\tname=\"viewport\"\n\t\tcontent=\"width=device-width\">\n</head>"
+        );
+    }
+}